@@ -1,6 +1,6 @@
 use std::{io::Error, sync::mpsc::RecvError};
 
-use etherparse::{ValueError, WriteError};
+use etherparse::{TcpOptionWriteError, ValueError, WriteError};
 
 #[derive(Debug)]
 pub enum TcpErr {
@@ -9,6 +9,7 @@ pub enum TcpErr {
     ValueError(ValueError),
     RecvError(RecvError),
     NixError(nix::Error),
+    OptionWriteError(TcpOptionWriteError),
 }
 
 impl From<Error> for TcpErr {
@@ -40,3 +41,9 @@ impl From<nix::Error> for TcpErr {
         Self::NixError(value)
     }
 }
+
+impl From<TcpOptionWriteError> for TcpErr {
+    fn from(value: TcpOptionWriteError) -> Self {
+        Self::OptionWriteError(value)
+    }
+}
@@ -2,25 +2,93 @@ pub mod err;
 pub mod tcp;
 
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     io::{self, Error, ErrorKind, Read, Write},
     net::Ipv4Addr,
-    ops::DerefMut,
+    ops::{DerefMut, RangeInclusive},
     sync::{Arc, Condvar, Mutex},
     thread,
+    time::{Duration, Instant},
 };
 
 use err::TcpErr;
 use nix::poll::{poll, EventFlags, PollFd};
 use std::os::fd::AsRawFd;
-use tcp::Connection;
+use tcp::{CongestionAlgorithm, Connection, IsnGenerator, Keepalive, KeyedIsn, SynCookies};
 
 //type InterfaceHandle = mpsc::Sender<InterfaceRequest>;
 type InterfaceHandle = Arc<Foobar>;
 
 type Result<T> = std::result::Result<T, TcpErr>;
 
-const SENDQUEUE_SIZE: usize = 1024;
+/// Bytes a stream buffers for sending, Nagle's algorithm needs room for
+/// more than a segment to coalesce writes into
+const SENDQUEUE_SIZE: usize = 64 << 10;
+
+/// IANA suggested range for dynamic ports, RFC 6335
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(75);
+/// MTUs IPv4 allows, RFC 791 has every host take 68 byte datagrams and the
+/// total length field stops at 65535
+const MTU_RANGE: RangeInclusive<usize> = 68..=65535;
+/// Longest an ACK may be delayed, RFC 1122 section 4.2.3.2
+const MAX_ACK_DELAY: Duration = Duration::from_millis(500);
+
+/// Tunables shared by every connection on an [`Interface`]
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Our address on tun0, the source of every `connect`
+    pub local_addr: Ipv4Addr,
+    /// Maximum Segment Lifetime, connections linger in TIME-WAIT for twice as long
+    pub msl: Duration,
+    /// How long a connection nobody holds any more waits in FIN-WAIT-2 for
+    /// the peer to say anything, like Linux's tcp_fin_timeout
+    pub fin_timeout: Duration,
+    /// Most resets sent per second for segments to ports nobody listens on,
+    /// `None` to answer every one of them
+    pub rst_rate_limit: Option<u32>,
+    /// MTU of tun0 between 68 and 65535, the MSS we advertise is derived from it
+    pub mtu: usize,
+    /// Congestion control new connections start out with
+    pub congestion_control: CongestionAlgorithm,
+    /// Bytes a connection buffers for the application to start out with, the
+    /// receive window never offers more than what's free of it
+    pub recv_buffer: usize,
+    /// Most a connection's receive buffer is grown to by auto-tuning
+    pub recv_buffer_max: usize,
+    /// Limit on the receive buffers of all connections together, auto-tuning
+    /// stops growing them once it's reached
+    pub recv_memory: usize,
+    /// Connections in SYN-RECEIVED a listener holds on to, SYNs past that are
+    /// answered with SYN cookies or dropped
+    pub syn_backlog: usize,
+    /// Answer SYNs with cookies once a listener's half-open queue is full
+    pub syn_cookies: bool,
+    /// How long an ACK may wait for a second segment or data to ride along,
+    /// `None` to ACK every segment right away. RFC 1122 caps it at 500ms.
+    pub ack_delay: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            // the kernel side of tun0 is 192.168.0.1, see run.sh
+            local_addr: Ipv4Addr::new(192, 168, 0, 2),
+            // RFC 9293 section 3.4.2
+            msl: Duration::from_secs(2 * 60),
+            fin_timeout: Duration::from_secs(60),
+            rst_rate_limit: Some(100),
+            mtu: 1500,
+            congestion_control: CongestionAlgorithm::default(),
+            recv_buffer: 128 << 10,
+            recv_buffer_max: 6 << 20,
+            recv_memory: 64 << 20,
+            syn_backlog: 256,
+            syn_cookies: true,
+            ack_delay: Some(Duration::from_millis(40)),
+        }
+    }
+}
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
 pub struct Quad {
@@ -32,21 +100,43 @@ fn packet_loop(mut nic: tun_tap::Iface, ih: InterfaceHandle) -> Result<()> {
     const TUNP_HEADER_LEN: usize = 0;
     const TCP_PROTO: u8 = 0x06;
 
-    let mut buf = [0u8; 1504];
+    let mut buf = vec![0u8; TUNP_HEADER_LEN + ih.manager.lock().unwrap().config.mtu];
 
+    let mut last_tick = Instant::now();
     loop {
         let pool_fd = PollFd::new(nic.as_raw_fd(), EventFlags::POLLIN);
-        let n = poll(&mut [pool_fd], 1)?;
-        if n == 0 {
+        let n = poll(&mut [pool_fd], tcp::TICK.as_millis() as i32)?;
+        // timers and pacing need to run while packets keep coming in too
+        if n == 0 || last_tick.elapsed() >= tcp::TICK {
+            last_tick = Instant::now();
             let mut cmg = ih.manager.lock().unwrap();
+            let mut aborted = false;
             for (quad, connection) in &mut cmg.connections {
+                let was_open = connection.check_error().is_ok();
                 if let Err(e) = connection.on_tick(&mut nic) {
                     eprintln!("quad:{:?}, tick_err:{:?}", quad, e);
                 }
+                aborted |= was_open && connection.check_error().is_err();
+            }
+            let cm = cmg.deref_mut();
+            cm.connections.retain(|_, c| !c.is_reapable(&cm.config));
+            let mut accepted = false;
+            for listener in cm.listeners.values_mut() {
+                accepted |= listener.promote(&mut cm.connections);
+            }
+            drop(cmg);
+            if accepted {
+                ih.pending_var.notify_all();
+            }
+            if aborted {
+                // a connection timed out, blocked callers need to see the error
+                ih.rcv_var.notify_all();
             }
             //println!("TIMERS");
             // Is little stupid here, loop over all connections
-            continue;
+            if n == 0 {
+                continue;
+            }
         }
         assert!(n == 1);
         // TODO set a timeout for this recv for TCP timer or ConnectionManager::terminate
@@ -94,7 +184,17 @@ fn packet_loop(mut nic: tun_tap::Iface, ih: InterfaceHandle) -> Result<()> {
                                 &buf[datai..nbyte],
                             )?;
 
+                            // completing the handshake puts it on the accept queue
+                            let accepted = match cm.listeners.get_mut(&q.dst.1) {
+                                Some(listener) if listener.syn_queue.contains(&q) => {
+                                    listener.promote(&mut cm.connections)
+                                }
+                                _ => false,
+                            };
                             drop(cmg);
+                            if accepted {
+                                ih.pending_var.notify_all();
+                            }
                             if a.contains(tcp::Available::Read) {
                                 ih.rcv_var.notify_all();
                             }
@@ -104,20 +204,62 @@ fn packet_loop(mut nic: tun_tap::Iface, ih: InterfaceHandle) -> Result<()> {
                             }
                         }
                         Entry::Vacant(entry) => {
-                            if let Some(pending) = cm.pending.get_mut(&tcph.destination_port()) {
-                                if let Some(c) = Connection::accpect(
-                                    &mut nic,
-                                    &ip_header,
-                                    &tcph,
-                                    &buf[datai..nbyte],
-                                )? {
-                                    entry.insert(c);
-                                    pending.push_back(q);
-                                    drop(cmg);
-                                    ih.pending_var.notify_all();
-
-                                    //TODO: wake up pending accept
+                            if let Some(listener) = cm.listeners.get_mut(&tcph.destination_port()) {
+                                let c = if listener.accept_queue.len() >= listener.backlog {
+                                    // nobody accepts, the peer retries its SYN or ACK
+                                    // until there's room again
+                                    None
+                                } else if !tcph.syn() {
+                                    // the ACK completing a handshake we answered with a cookie
+                                    Connection::from_cookie(
+                                        &mut nic,
+                                        &ip_header,
+                                        &tcph,
+                                        &buf[datai..nbyte],
+                                        &q,
+                                        &cm.syn_cookies,
+                                        &cm.config,
+                                    )?
+                                } else if listener.syn_queue.len() >= cm.config.syn_backlog {
+                                    // the half-open queue is full, as it would be in a SYN
+                                    // flood, without cookies the peer has to try again later
+                                    if cm.config.syn_cookies {
+                                        tcp::send_syn_cookie(
+                                            &mut nic,
+                                            &ip_header,
+                                            &tcph,
+                                            &q,
+                                            &mut cm.syn_cookies,
+                                            &cm.config,
+                                        )?;
+                                    }
+                                    None
+                                } else {
+                                    Connection::accpect(
+                                        &mut nic,
+                                        &ip_header,
+                                        &tcph,
+                                        &buf[datai..nbyte],
+                                        cm.isn.isn(&q),
+                                        &cm.config,
+                                    )?
                                 };
+
+                                if let Some(c) = c {
+                                    // only a cookie gets us past the handshake right away
+                                    let established = c.state.is_synchronized();
+                                    entry.insert(c);
+                                    if established {
+                                        listener.accept_queue.push_back(q);
+                                        drop(cmg);
+                                        ih.pending_var.notify_all();
+                                    } else {
+                                        listener.syn_queue.insert(q);
+                                    }
+                                }
+                            } else if cm.allow_rst() {
+                                // nobody is listening, answer as the CLOSED state would
+                                tcp::send_rst(&mut nic, &ip_header, &tcph, &buf[datai..nbyte])?;
                             }
                         }
                     }
@@ -128,7 +270,6 @@ fn packet_loop(mut nic: tun_tap::Iface, ih: InterfaceHandle) -> Result<()> {
     }
 }
 
-#[derive(Default)]
 struct Foobar {
     manager: Mutex<ConnectionManager>,
     pending_var: Condvar,
@@ -153,7 +294,46 @@ impl Drop for Interface {
 
 impl Interface {
     pub fn new() -> io::Result<Self> {
-        let tx: InterfaceHandle = Arc::default();
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> io::Result<Self> {
+        Self::with_isn_generator(config, KeyedIsn::new())
+    }
+
+    /// An interface that takes the initial sequence numbers of its
+    /// connections from `isn` instead of RFC 6528's keyed hash
+    pub fn with_isn_generator(
+        config: Config,
+        isn: impl IsnGenerator + 'static,
+    ) -> io::Result<Self> {
+        if !MTU_RANGE.contains(&config.mtu) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("mtu {} is outside of {:?}", config.mtu, MTU_RANGE),
+            ));
+        }
+        if config.ack_delay.is_some_and(|delay| delay > MAX_ACK_DELAY) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("ack_delay is over {:?}", MAX_ACK_DELAY),
+            ));
+        }
+
+        let tx: InterfaceHandle = Arc::new(Foobar {
+            manager: Mutex::new(ConnectionManager {
+                config,
+                isn: Box::new(isn),
+                terminated: false,
+                connections: Default::default(),
+                listeners: Default::default(),
+                syn_cookies: SynCookies::new(),
+                next_ephemeral: 0,
+                rst_sent: None,
+            }),
+            pending_var: Default::default(),
+            rcv_var: Default::default(),
+        });
 
         let nic = tun_tap::Iface::without_packet_info("tun0", tun_tap::Mode::Tun)?;
         let jh = {
@@ -175,11 +355,18 @@ impl Interface {
     }
 
     pub fn bind(&mut self, port: u16) -> io::Result<TcpListener> {
+        self.bind_with_backlog(port, DEFAULT_BACKLOG)
+    }
+
+    /// Listens on `port`, holding at most `backlog` established connections
+    /// for `accept`. SYNs past that are dropped until `accept` makes room.
+    /// A backlog of 0 is taken as 1, as `listen(2)` does.
+    pub fn bind_with_backlog(&mut self, port: u16, backlog: usize) -> io::Result<TcpListener> {
         use std::collections::hash_map::Entry;
         let mut cm = self.ih.as_ref().unwrap().manager.lock().unwrap();
-        match cm.pending.entry(port) {
+        match cm.listeners.entry(port) {
             Entry::Vacant(v) => {
-                v.insert(Default::default());
+                v.insert(Listener::new(backlog.max(1)));
             }
             Entry::Occupied(_) => {
                 return Err(Error::new(
@@ -195,13 +382,165 @@ impl Interface {
             ih: self.ih.as_ref().unwrap().clone(),
         })
     }
+
+    pub fn connect(&mut self, addr: (Ipv4Addr, u16)) -> io::Result<TcpStream> {
+        let ih = self.ih.as_ref().unwrap();
+        let mut cm = ih.manager.lock().unwrap();
+        let port = cm.ephemeral_port(addr).ok_or_else(|| {
+            Error::new(ErrorKind::AddrNotAvailable, "no ephemeral port available")
+        })?;
+        let quad = Quad {
+            src: addr,
+            dst: (cm.config.local_addr, port),
+        };
+        let iss = cm.isn.isn(&quad);
+        let c = Connection::connect(quad, iss, &cm.config);
+        cm.connections.insert(quad, c);
+
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            let c = cm.connections.get(&quad).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::ConnectionAborted,
+                    "stream was terminated unexpectedly",
+                )
+            })?;
+
+            if let Err(e) = c.check_error() {
+                cm.connections.remove(&quad);
+                return Err(e);
+            }
+
+            if c.state.is_synchronized() {
+                return Ok(TcpStream {
+                    quad,
+                    ih: ih.clone(),
+                });
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                cm.connections.remove(&quad);
+                return Err(Error::new(ErrorKind::TimedOut, "connection timed out"));
+            }
+            cm = ih.rcv_var.wait_timeout(cm, deadline - now).unwrap().0;
+        }
+    }
+}
+
+/// Connections `accept` hands out when `bind` doesn't say otherwise
+const DEFAULT_BACKLOG: usize = 128;
+
+/// A bound port
+struct Listener {
+    /// Connections still in the handshake, or past it and waiting for room
+    /// in `accept_queue`
+    syn_queue: HashSet<Quad>,
+    /// Established connections for `accept`, in the order they got there
+    accept_queue: VecDeque<Quad>,
+    /// How long `accept_queue` may get
+    backlog: usize,
+}
+
+impl Listener {
+    fn new(backlog: usize) -> Self {
+        Listener {
+            syn_queue: Default::default(),
+            accept_queue: Default::default(),
+            backlog,
+        }
+    }
+
+    /// Moves connections that completed the handshake over to the accept
+    /// queue as far as the backlog allows, returns whether any got there
+    fn promote(&mut self, connections: &mut HashMap<Quad, Connection>) -> bool {
+        let mut promoted = false;
+        self.syn_queue.retain(|quad| {
+            let Some(c) = connections.get_mut(quad) else {
+                return false;
+            };
+            if c.state == tcp::State::SynRcvd {
+                return true;
+            }
+            if !c.state.is_synchronized() {
+                // reset or timed out during the handshake, nobody will ever see it
+                c.detached = true;
+                return false;
+            }
+            if self.accept_queue.len() >= self.backlog {
+                return true;
+            }
+            self.accept_queue.push_back(*quad);
+            promoted = true;
+            false
+        });
+        promoted
+    }
 }
 
-#[derive(Default)]
 pub struct ConnectionManager {
+    config: Config,
+    /// Where the ISS of new connections comes from
+    isn: Box<dyn IsnGenerator>,
     terminated: bool,
     connections: HashMap<Quad, Connection>,
-    pending: HashMap<u16, VecDeque<Quad>>,
+    listeners: HashMap<u16, Listener>,
+    syn_cookies: SynCookies,
+    next_ephemeral: u16,
+    /// Start of the current rate limiting second and resets sent in it
+    rst_sent: Option<(Instant, u32)>,
+}
+
+impl ConnectionManager {
+    /// Grows the receive buffer of `quad` towards `wanted`, as far as the
+    /// limits on it and on all receive buffers together allow
+    fn grow_rcv_buffer(&mut self, quad: Quad, wanted: usize) {
+        let in_use: usize = self.connections.values().map(Connection::rcv_buffer).sum();
+        let headroom = self.config.recv_memory.saturating_sub(in_use);
+        if let Some(c) = self.connections.get_mut(&quad) {
+            let size = wanted
+                .min(c.rcv_buffer() + headroom)
+                .min(self.config.recv_buffer_max);
+            c.grow_rcv_buffer(size);
+        }
+    }
+
+    fn ephemeral_port(&mut self, remote: (Ipv4Addr, u16)) -> Option<u16> {
+        let (start, end) = (*EPHEMERAL_PORTS.start(), *EPHEMERAL_PORTS.end());
+        let n = end - start + 1;
+        for _ in 0..n {
+            let port = start + self.next_ephemeral % n;
+            self.next_ephemeral = self.next_ephemeral.wrapping_add(1);
+
+            let quad = Quad {
+                src: remote,
+                dst: (self.config.local_addr, port),
+            };
+            if !self.listeners.contains_key(&port) && !self.connections.contains_key(&quad) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    fn allow_rst(&mut self) -> bool {
+        let Some(limit) = self.config.rst_rate_limit else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let (since, sent) = match self.rst_sent {
+            Some((since, sent)) if now.duration_since(since) < Duration::from_secs(1) => {
+                (since, sent)
+            }
+            _ => (now, 0),
+        };
+        if sent >= limit {
+            return false;
+        }
+        self.rst_sent = Some((since, sent + 1));
+        true
+    }
 }
 pub struct TcpStream {
     quad: Quad,
@@ -220,6 +559,7 @@ impl Read for TcpStream {
                     "stream was terminated unexpectedly",
                 )
             })?;
+            c.check_error()?;
 
             if c.is_rcv_closed() && c.incoming.is_empty() {
                 // no more data to read, and no need to block, because there won't be any more
@@ -227,8 +567,6 @@ impl Read for TcpStream {
             }
 
             if !c.incoming.is_empty() {
-                // TODO detect FIN and return nread == 0;
-
                 let mut nread = 0;
                 let (head, tail) = c.incoming.as_slices();
                 for slice in [head, tail] {
@@ -237,6 +575,9 @@ impl Read for TcpStream {
                     nread += read;
                 }
                 drop(c.incoming.drain(..nread));
+                if let Some(wanted) = c.on_read(nread) {
+                    cm.grow_rcv_buffer(self.quad, wanted);
+                }
                 return Ok(nread);
             }
             cm = self.ih.rcv_var.wait(cm).unwrap();
@@ -253,6 +594,11 @@ impl Write for TcpStream {
                 "stream was terminated unexpectedly",
             )
         })?;
+        c.check_error()?;
+
+        if c.closed {
+            return Err(Error::new(ErrorKind::BrokenPipe, "stream was shut down"));
+        }
 
         if c.unacked.len() >= SENDQUEUE_SIZE {
             //TODO block
@@ -260,7 +606,7 @@ impl Write for TcpStream {
         }
 
         let nwrite = SENDQUEUE_SIZE
-            .saturating_add(c.unacked.len())
+            .saturating_sub(c.unacked.len())
             .min(buf.len());
         c.unacked.extend(&buf[..nwrite]);
 
@@ -276,6 +622,7 @@ impl Write for TcpStream {
                 "stream was terminated unexpectedly",
             )
         })?;
+        c.check_error()?;
 
         if c.unacked.is_empty() {
             Ok(())
@@ -288,12 +635,16 @@ impl Write for TcpStream {
 
 impl Drop for TcpStream {
     fn drop(&mut self) {
-        // TODO send FIN on cm.connections(quad)
-        // TODO eventually remove self.quad from cm.connections
-        // let mut cm = self.ih.manager.lock().unwrap();
-        // if let Some(_c) = cm.connections.remove(&self.quad) {
-        //     //  unimplemented!("send FIN on c")
-        // }
+        let mut cm = self.ih.manager.lock().unwrap();
+        if let Some(c) = cm.connections.get_mut(&self.quad) {
+            // the packet loop sends our FIN and finishes the close,
+            // the connection is reaped from its tick once that is done
+            let _ = c.close();
+            c.detached = true;
+            if c.state == tcp::State::Closed {
+                cm.connections.remove(&self.quad);
+            }
+        }
     }
 }
 
@@ -307,8 +658,69 @@ impl TcpStream {
             )
         })?;
 
+        c.check_error()?;
         c.close()
     }
+
+    /// Switches this connection over to `algorithm`, starting from a fresh window
+    pub fn set_congestion_control(&self, algorithm: CongestionAlgorithm) -> io::Result<()> {
+        let mut cm = self.ih.manager.lock().unwrap();
+        let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
+            Error::new(
+                ErrorKind::ConnectionAborted,
+                "stream was terminated unexpectedly",
+            )
+        })?;
+
+        c.check_error()?;
+        c.set_congestion_control(algorithm);
+        Ok(())
+    }
+
+    /// Turns Nagle's algorithm off, so small writes go out without waiting
+    /// for the ACK of what's in flight
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        let mut cm = self.ih.manager.lock().unwrap();
+        let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
+            Error::new(
+                ErrorKind::ConnectionAborted,
+                "stream was terminated unexpectedly",
+            )
+        })?;
+
+        c.check_error()?;
+        c.nodelay = nodelay;
+        Ok(())
+    }
+
+    /// Probes the peer once the connection has been idle for a while, so one
+    /// that went away without a word times out. `None` turns it off.
+    pub fn set_keepalive(&self, keepalive: Option<Keepalive>) -> io::Result<()> {
+        let mut cm = self.ih.manager.lock().unwrap();
+        let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
+            Error::new(
+                ErrorKind::ConnectionAborted,
+                "stream was terminated unexpectedly",
+            )
+        })?;
+
+        c.check_error()?;
+        c.keepalive = keepalive;
+        Ok(())
+    }
+
+    pub fn nodelay(&self) -> io::Result<bool> {
+        let cm = self.ih.manager.lock().unwrap();
+        let c = cm.connections.get(&self.quad).ok_or_else(|| {
+            Error::new(
+                ErrorKind::ConnectionAborted,
+                "stream was terminated unexpectedly",
+            )
+        })?;
+
+        c.check_error()?;
+        Ok(c.nodelay)
+    }
 }
 
 pub struct TcpListener {
@@ -319,14 +731,24 @@ pub struct TcpListener {
 impl Drop for TcpListener {
     fn drop(&mut self) {
         let mut cm = self.ih.manager.lock().unwrap();
-        let pending = cm
-            .pending
+        let listener = cm
+            .listeners
             .remove(&self.port)
             .expect("port closed while listener still active");
 
-        for _quad in pending {
-            //TODO terminated cm.connections[quad]
-            unimplemented!("terminated cm.connections[quad]")
+        // a close would take a half-open connection out of SYN-RECEIVED
+        // without ever completing the handshake, it's reset instead
+        for quad in listener.syn_queue {
+            if let Some(c) = cm.connections.get_mut(&quad) {
+                c.reset();
+                c.detached = true;
+            }
+        }
+        for quad in listener.accept_queue {
+            if let Some(c) = cm.connections.get_mut(&quad) {
+                let _ = c.close();
+                c.detached = true;
+            }
         }
     }
 }
@@ -335,12 +757,14 @@ impl TcpListener {
     pub fn accept(&mut self) -> Result<TcpStream> {
         let mut cm = self.ih.manager.lock().unwrap();
         loop {
-            if let Some(quad) = cm
-                .pending
+            let manager = cm.deref_mut();
+            let listener = manager
+                .listeners
                 .get_mut(&self.port)
-                .expect("port closed while listener still active")
-                .pop_front()
-            {
+                .expect("port closed while listener still active");
+            if let Some(quad) = listener.accept_queue.pop_front() {
+                // there's room for whoever completed the handshake in the meantime
+                listener.promote(&mut manager.connections);
                 return Ok(TcpStream {
                     quad,
                     ih: self.ih.clone(),
@@ -1,13 +1,38 @@
 use bitflags::bitflags;
 use std::{
     collections::{BTreeMap, VecDeque},
-    io::ErrorKind,
+    io::{self, ErrorKind},
     time::{Duration, Instant},
 };
 
-use etherparse::{ip_number, Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice};
+use etherparse::{
+    ip_number, Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice, TcpOptionElement,
+};
+
+use crate::{err::TcpErr, Config, Quad};
+
+use self::{
+    rack::{Rack, Transmission},
+    rate::RateSampler,
+    rcv_space::RcvSpace,
+    reassembly::Reassembly,
+    sack::Scoreboard,
+};
+
+pub use self::{
+    congestion::{Ack, CongestionAlgorithm, CongestionControl, RateSample},
+    isn::{IsnGenerator, KeyedIsn},
+    syn_cookie::SynCookies,
+};
 
-use crate::err::TcpErr;
+mod congestion;
+mod isn;
+mod rack;
+mod rate;
+mod rcv_space;
+mod reassembly;
+mod sack;
+mod syn_cookie;
 
 bitflags! {
     pub struct Available: u32 {
@@ -18,33 +43,156 @@ bitflags! {
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum State {
-    //Closed,
+    Closed,
     //Listen,
+    SynSent,
     SynRcvd,
     Estab,
     FinWait1,
     FinWait2,
+    CloseWait,
     Closing,
+    LastAck,
     TimeWait,
 }
 
 impl State {
     pub fn is_synchronized(&self) -> bool {
         match self {
-            State::Estab | State::FinWait1 | State::Closing | State::FinWait2 | Self::TimeWait => {
-                true
-            }
-            State::SynRcvd => false,
+            State::Estab
+            | State::FinWait1
+            | State::Closing
+            | State::FinWait2
+            | State::CloseWait
+            | State::LastAck
+            | Self::TimeWait => true,
+            State::Closed | State::SynSent | State::SynRcvd => false,
         }
     }
 }
 
+/// When to probe an idle connection, RFC 1122 section 4.2.3.6
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// How long nothing has to arrive before the first probe
+    pub idle: Duration,
+    /// Time between unanswered probes
+    pub interval: Duration,
+    /// Unanswered probes after which the connection times out
+    pub probes: u32,
+}
+
+impl Default for Keepalive {
+    /// Linux's defaults, RFC 1122 asks for at least two hours of idle time
+    fn default() -> Self {
+        Keepalive {
+            idle: Duration::from_secs(2 * 60 * 60),
+            interval: Duration::from_secs(75),
+            probes: 9,
+        }
+    }
+}
+
+/// What a SYN offered that a passive open needs to know about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SynOptions {
+    /// The peer's MSS
+    pub mss: usize,
+    /// Shift count the peer applies to its windows, `None` without scaling
+    pub wscale: Option<u8>,
+    pub sack_permitted: bool,
+}
+
+impl SynOptions {
+    pub fn from_header(tcp_header: &TcpHeaderSlice) -> Self {
+        SynOptions {
+            mss: peer_mss(tcp_header),
+            // window scaling is only on if both SYNs carry the option
+            wscale: options(tcp_header).find_map(|o| match o {
+                TcpOptionElement::WindowScale(shift) => Some(shift.min(MAX_WINDOW_SHIFT)),
+                _ => None,
+            }),
+            sack_permitted: options(tcp_header)
+                .any(|o| o == TcpOptionElement::SelectiveAcknowledgementPermitted),
+        }
+    }
+}
+
+/// What a passive open learns from the peer's SYN
+struct PeerSyn {
+    irs: u32,
+    wnd: u32,
+    options: SynOptions,
+    /// TSval, `None` if the peer doesn't do timestamps
+    tsval: Option<u32>,
+}
+
+/// Largest shift count allowed by RFC 7323
+const MAX_WINDOW_SHIFT: u8 = 14;
+/// Largest window we can advertise, the receive buffer is capped by it
+const MAX_WINDOW: usize = (u16::MAX as usize) << MAX_WINDOW_SHIFT;
+
+/// Smallest shift count that lets `wnd` fit the 16 bit window field
+fn window_shift(wnd: u32) -> u8 {
+    let mut shift = 0;
+    while shift < MAX_WINDOW_SHIFT && wnd >> shift > u16::MAX as u32 {
+        shift += 1;
+    }
+    shift
+}
+
+/// The receive buffer a connection starts with, and the shift count for the
+/// largest window auto-tuning may grow it to
+fn rcv_buffer_and_shift(config: &Config) -> (usize, u8) {
+    let rcv_buffer = config.recv_buffer.min(MAX_WINDOW);
+    let rcv_shift = window_shift(config.recv_buffer_max.max(rcv_buffer).min(MAX_WINDOW) as u32);
+    (rcv_buffer, rcv_shift)
+}
+
+/// Bytes of IPv4 and TCP header without options, an MSS doesn't count them
+const HEADERS_LEN: usize = 40;
+/// MSS assumed when the peer's SYN doesn't carry the option, RFC 9293 section 3.7.1
+const DEFAULT_MSS: usize = 536;
+/// Smallest MSS we take from a peer, as Linux's tcp_min_snd_mss. Anything
+/// less leaves no room for payload once options are in.
+const MIN_MSS: usize = 48;
+
+/// How often the packet loop calls `on_tick`
+pub(crate) const TICK: Duration = Duration::from_millis(1);
+
+/// RTO before the first RTT measurement, RFC 6298 section 2.1
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MIN_RTO: Duration = Duration::from_secs(1);
+const MAX_RTO: Duration = Duration::from_secs(60);
+/// Clock granularity G
+const CLOCK_GRANULARITY: Duration = TICK;
+/// Timeouts in a row after which we give up on the peer
+const MAX_RETRANSMITS: u32 = 15;
+/// Timeouts in SYN-RECEIVED after which the half-open connection gives up
+/// its place in the SYN queue, as Linux's tcp_synack_retries
+const MAX_SYNACK_RETRANSMITS: u32 = 5;
+/// Duplicate ACKs that make us retransmit without waiting for the timer
+const DUPACK_THRESHOLD: u32 = 3;
+/// WCDelAckT, how long the peer may sit on the ACK for a lone segment
+const WORST_CASE_DELAYED_ACK: Duration = Duration::from_millis(200);
+/// Shortest probe timeout, so ticks and ACK scheduling don't trigger probes
+const MIN_PTO: Duration = Duration::from_millis(10);
+/// Segments acked right away at the start and after out of order data, as Linux does
+const MAX_QUICKACKS: u32 = 16;
+
 impl Connection {
+    /// Active open, the SYN is only queued here and goes out on the next `on_tick`
+    pub fn connect(quad: Quad, iss: u32, config: &Config) -> Self {
+        Self::new(State::SynSent, &quad, iss, None, config)
+    }
+
     pub fn accpect(
         ipface: &mut tun_tap::Iface,
         ip_header: &Ipv4HeaderSlice,
         tcp_header: &TcpHeaderSlice,
         _data: &[u8],
+        iss: u32,
+        config: &Config,
     ) -> Result<Option<Self>, TcpErr> {
         // eprintln!(
         //     "Got packet fin:{}, se:{}, ack:{}",
@@ -57,107 +205,435 @@ impl Connection {
             return Ok(None);
         }
 
-        let iss = 0;
-        let mut connecton = Connection {
-            state: State::SynRcvd,
+        let syn = SynOptions::from_header(tcp_header);
+        let mut connecton = Self::passive(
+            ip_header,
+            tcp_header,
+            tcp_header.sequence_number(),
+            iss,
+            &syn,
+            config,
+        );
+
+        connecton.tcp.acknowledgment_number = connecton.rcv.nxt;
+        connecton.tcp.syn = true;
+        connecton.tcp.ack = true;
+
+        connecton.write(ipface, connecton.snd.nxt, 0)?;
+
+        Ok(Some(connecton))
+    }
+
+    /// Connection in SYN-RECEIVED for the SYN `syn` describes. `tcp_header`
+    /// is the segment that got us here and carries the peer's timestamps.
+    fn passive(
+        ip_header: &Ipv4HeaderSlice,
+        tcp_header: &TcpHeaderSlice,
+        irs: u32,
+        iss: u32,
+        syn: &SynOptions,
+        config: &Config,
+    ) -> Self {
+        let quad = Quad {
+            src: (ip_header.source_addr(), tcp_header.source_port()),
+            dst: (ip_header.destination_addr(), tcp_header.destination_port()),
+        };
+        let peer = PeerSyn {
+            irs,
+            // the window in a SYN is never scaled
+            wnd: tcp_header.window_size() as u32,
+            options: *syn,
+            tsval: timestamp(tcp_header).map(|(tsval, _)| tsval),
+        };
+        Self::new(State::SynRcvd, &quad, iss, Some(&peer), config)
+    }
+
+    /// Both opens end up here, `peer` is what the peer's SYN told us if we
+    /// have seen it. Until we have, everything we offer counts as agreed on.
+    fn new(state: State, quad: &Quad, iss: u32, peer: Option<&PeerSyn>, config: &Config) -> Self {
+        let (rcv_buffer, rcv_shift) = rcv_buffer_and_shift(config);
+        let window_scaling = peer.is_none_or(|peer| peer.options.wscale.is_some());
+        // without scaling the window field is all we can offer
+        let rcv_wnd = if window_scaling {
+            rcv_buffer
+        } else {
+            rcv_buffer.min(u16::MAX as usize)
+        };
+        let mss = peer.map_or(usize::MAX, |peer| peer.options.mss);
+        let mss = mss.min(config.mtu - HEADERS_LEN);
+        Connection {
+            state,
             rcv: ReceiveSequenceSpace {
-                irs: tcp_header.sequence_number(),
-                nxt: tcp_header.sequence_number() + 1,
-                wnd: tcp_header.window_size(),
+                irs: peer.map_or(0, |peer| peer.irs),
+                nxt: peer.map_or(0, |peer| peer.irs.wrapping_add(1)),
+                wnd: rcv_wnd as u32,
+                wscale: if window_scaling { rcv_shift } else { 0 },
                 up: 0,
             },
             snd: SendSeuquenceSpace {
                 iss,
                 una: iss,
                 nxt: iss,
-                wnd: tcp_header.window_size(),
+                wnd: peer.map_or(0, |peer| peer.wnd),
+                wscale: peer.and_then(|peer| peer.options.wscale).unwrap_or(0),
                 up: 0,
-                wl1: 0,
+                wl1: peer.map_or(0, |peer| peer.irs),
                 wl2: 0,
             },
             ip: Ipv4Header::new(
                 0,
                 64,
                 ip_number::TCP,
-                ip_header.destination(),
-                ip_header.source(),
-            ),
-
-            tcp: TcpHeader::new(
-                tcp_header.destination_port(),
-                tcp_header.source_port(),
-                iss,
-                tcp_header.window_size(),
+                quad.dst.0.octets(),
+                quad.src.0.octets(),
             ),
-            timer: Timer {
-                send_times: Default::default(),
-                srtt: Duration::from_secs(2 * 60),
-            },
+            tcp: TcpHeader::new(quad.dst.1, quad.src.1, iss, 0),
+            timer: Timer::new(),
             incoming: Default::default(),
+            reassembly: Default::default(),
             unacked: Default::default(),
             closed: Default::default(),
             closed_at: Default::default(),
-        };
-
-        connecton.tcp.acknowledgment_number = connecton.rcv.nxt;
-        connecton.tcp.syn = true;
-        connecton.tcp.ack = true;
+            detached: false,
+            error: None,
+            sack_permitted: peer.is_none_or(|peer| peer.options.sack_permitted),
+            scoreboard: Default::default(),
+            window_scaling,
+            timestamps: peer.is_none_or(|peer| peer.tsval.is_some()),
+            ts_recent: peer.and_then(|peer| peer.tsval).unwrap_or(0),
+            last_ack_sent: 0,
+            mtu: config.mtu,
+            mss,
+            congestion_algorithm: config.congestion_control,
+            congestion: config.congestion_control.build(mss),
+            rate: RateSampler::new(),
+            next_send: Instant::now(),
+            dupacks: 0,
+            recover: None,
+            in_recovery: false,
+            inflation: 0,
+            rack: Default::default(),
+            tlp_end: None,
+            rcv_buffer,
+            rcv_space: RcvSpace::new(),
+            window_update: false,
+            nodelay: false,
+            ack_delay: config.ack_delay,
+            delayed_segments: 0,
+            quickacks: MAX_QUICKACKS,
+            keepalive: None,
+            reset: false,
+        }
+    }
 
-        connecton.write(ipface, connecton.snd.nxt, 0)?;
+    /// Completes a passive open we answered with a SYN cookie, `None` unless
+    /// `tcp_header` is an ACK of a cookie we made, RFC 4987 section 3.6
+    pub fn from_cookie(
+        nic: &mut tun_tap::Iface,
+        ip_header: &Ipv4HeaderSlice,
+        tcp_header: &TcpHeaderSlice,
+        data: &[u8],
+        quad: &Quad,
+        cookies: &SynCookies,
+        config: &Config,
+    ) -> Result<Option<Self>, TcpErr> {
+        if !tcp_header.ack() || tcp_header.syn() || tcp_header.rst() {
+            return Ok(None);
+        }
+        let irs = tcp_header.sequence_number().wrapping_sub(1);
+        let iss = tcp_header.acknowledgment_number().wrapping_sub(1);
+        let Some(syn) = cookies.decode(quad, irs, iss) else {
+            return Ok(None);
+        };
 
-        Ok(Some(connecton))
+        let mut connection = Self::passive(ip_header, tcp_header, irs, iss, &syn, config);
+        // our SYN-ACK's TSval was taken from the cookies' clock
+        connection.timer.ts_origin = cookies.ts_origin();
+        // our SYN-ACK went out with the cookie, this ACK is the reply to it
+        connection.snd.nxt = iss.wrapping_add(1);
+        connection.tcp.ack = true;
+        connection.on_packet(nic, ip_header, tcp_header, data)?;
+        Ok(Some(connection))
     }
 
     pub fn on_tick(&mut self, nic: &mut tun_tap::Iface) -> Result<(), TcpErr> {
+        if State::Closed == self.state || State::TimeWait == self.state {
+            // nothing left for us to send
+            return Ok(());
+        }
+
+        if self.reset {
+            // RFC 9293 section 3.10.7.1, <SEQ=SND.NXT><CTL=RST>, with the ACK
+            // a peer still in SYN-SENT needs to believe it
+            self.tcp.rst = true;
+            self.write(nic, self.snd.nxt, 0)?;
+            self.tcp.rst = false;
+            self.abort(ErrorKind::ConnectionAborted);
+            return Ok(());
+        }
+
         let nunacked = self.snd.nxt.wrapping_sub(self.snd.una) as usize;
-        let unsent = self.unacked.len() - nunacked;
 
-        let waited_for = self
-            .timer
-            .send_times
-            .range(self.snd.una..)
-            .next()
-            .map(|(_, v)| v.elapsed());
-        let should_retransmit = if let Some(waited_for) = waited_for {
-            waited_for > Duration::from_secs(1) && waited_for > self.timer.srtt.mul_f32(1.5)
-        } else {
-            false
-        };
+        let should_retransmit = self.timer.is_expired();
         if should_retransmit {
-            let resend = self.unacked.len().min(self.snd.wnd as usize);
-            if resend < self.snd.wnd.into() && self.closed {
-                self.tcp.fin = true;
-                self.closed_at = Some(self.snd.una + self.unacked.len() as u32);
-                //TODO set self.close_at
-            }
-            self.write(nic, self.snd.una, resend)?;
-            self.snd.nxt = self.snd.una.wrapping_add(resend as u32);
-        } else {
-            // we should send new data if we have new data and space in the window
-            if unsent == 0 && self.closed_at.is_some() {
+            self.timer.retransmits += 1;
+            let limit = if self.state == State::SynRcvd {
+                MAX_SYNACK_RETRANSMITS
+            } else {
+                MAX_RETRANSMITS
+            };
+            if self.timer.retransmits > limit {
+                self.abort(ErrorKind::TimedOut);
                 return Ok(());
             }
+            self.timer.back_off();
+            self.congestion.on_timeout(nunacked);
+            // RFC 6582 section 4, don't take the ACKs for what's in flight
+            // now for a new loss
+            self.recover = Some(self.snd.nxt);
+            self.in_recovery = false;
+            self.inflation = 0;
+            self.dupacks = 0;
+            self.timer.pto = None;
+            self.tlp_end = None;
+            // RFC 8985 section 6.3, all that isn't SACKed is lost, the ACKs
+            // for what we send again clock out the rest of it
+            for t in self.timer.send_times.values_mut() {
+                t.lost |= !t.sacked;
+            }
+        }
+        if let Some(keepalive) = self.keepalive {
+            // only an idle connection is probed, the RTO watches over data in flight
+            let idle = matches!(self.state, State::Estab | State::CloseWait) && nunacked == 0;
+            let probes = self.timer.keepalive_probes;
+            // a time too far off to represent never comes
+            let due = keepalive
+                .interval
+                .checked_mul(probes)
+                .and_then(|wait| wait.checked_add(keepalive.idle))
+                .and_then(|wait| self.timer.last_received.checked_add(wait));
+            if idle && due.is_some_and(|due| due <= Instant::now()) {
+                if probes >= keepalive.probes {
+                    self.abort(ErrorKind::TimedOut);
+                    return Ok(());
+                }
+                // a byte the peer already has, its ACK tells us it's still there
+                self.write(nic, self.snd.nxt.wrapping_sub(1), 0)?;
+                self.timer.keepalive_probes += 1;
+            }
+        }
+        if !self.state.is_synchronized() {
+            // nothing but our SYN may go out before the handshake completes,
+            // `connect` only queues it so the first one is sent from here too
+            if self.snd.nxt == self.snd.iss || should_retransmit {
+                self.tcp.syn = true;
+                self.write(nic, self.snd.una, 0)?;
+            }
+            return Ok(());
+        }
 
-            let allowed = self.snd.wnd as usize - nunacked;
-            if allowed == 0 {
-                return Ok(());
+        let now = Instant::now();
+        if self.window_update || self.timer.delayed_ack.is_some_and(|at| at <= now) {
+            // the application read from a window it had closed or nearly so,
+            // or no second segment or data came along to carry the ACK
+            self.write(nic, self.snd.nxt, 0)?;
+        }
+
+        if should_retransmit {
+            // with a congestion window of one segment only the earliest goes
+            // out again, SND.UNA always starts a hole
+            self.write(nic, self.snd.una, nunacked)?;
+        } else {
+            if self.timer.pto.is_some_and(|pto| pto <= now) {
+                self.send_probe(nic)?;
+            }
+            if self.rack.timeout.is_some_and(|timeout| timeout <= now) {
+                self.detect_loss(now);
+            }
+            let lost: Vec<_> = self
+                .timer
+                .send_times
+                .iter()
+                .filter(|(_, t)| t.lost)
+                .map(|(&seq, t)| (seq, t.end))
+                .collect();
+            // retransmissions come out of the congestion window as new data
+            // does, with SACK the scoreboard tells what left the network and
+            // duplicate ACKs don't need to inflate the window
+            let inflation = if self.sack_permitted {
+                0
+            } else {
+                self.inflation
+            };
+            let mut budget = (self.congestion.cwnd() + inflation).saturating_sub(self.pipe());
+            for (start, end) in lost {
+                if budget == 0 {
+                    break;
+                }
+                self.retransmit(nic, start, end)?;
+                budget = budget.saturating_sub(end.wrapping_sub(start) as usize);
+            }
+
+            // a zero window with nothing in flight would never be reopened
+            // if the peer's window update got lost, RFC 9293 section 3.8.6.1
+            let unsent = self.unacked.len().saturating_sub(nunacked);
+            let pending = unsent > 0 || self.closed_at == Some(self.snd.nxt);
+            if self.snd.wnd == 0 && nunacked == 0 && pending {
+                match self.timer.persist {
+                    Some(deadline) if deadline <= now => {
+                        // a byte the peer already has, its ACK carries the window
+                        self.write(nic, self.snd.una.wrapping_sub(1), 0)?;
+                        self.timer.persist_backoff += 1;
+                        self.timer.arm_persist(now);
+                    }
+                    Some(_) => {}
+                    None => self.timer.arm_persist(now),
+                }
+            } else {
+                self.timer.persist = None;
+                self.timer.persist_backoff = 0;
             }
 
-            let send = unsent.min(allowed);
-            if send < allowed && self.closed && self.closed_at.is_none() {
-                self.tcp.fin = true;
-                self.closed_at = Some(self.snd.nxt.wrapping_add(unsent as u32));
+            // we should send new data if we have new data and space in the window
+            let mut allowed = (self.snd.wnd as usize).saturating_sub(nunacked).min(budget);
+            let pacing_rate = self.congestion.pacing_rate();
+            let now = Instant::now();
+            while allowed > 0 {
+                let sent_ahead = self.snd.nxt.wrapping_sub(self.snd.una) as usize;
+                let unsent = self.unacked.len().saturating_sub(sent_ahead);
+                if unsent == 0 && self.closed_at != Some(self.snd.nxt) {
+                    // whatever the ACKs tell about the path now, we didn't use all of it
+                    self.rate.on_app_limited(sent_ahead);
+                    break;
+                }
+                if pacing_rate.is_some() && self.next_send > now {
+                    break;
+                }
+                // Nagle, RFC 9293 section 3.7.4, a small segment waits for the
+                // ACK of what's in flight so it can grow. The FIN may not come
+                // before data is there, so it never waits.
+                let small = unsent.min(allowed) < self.full_segment();
+                let last = self.closed_at == Some(self.snd.nxt.wrapping_add(unsent as u32));
+                if !self.nodelay && small && sent_ahead > 0 && !last {
+                    break;
+                }
+
+                let sent = self.write(nic, self.snd.nxt, unsent.min(allowed))?;
+                if sent == 0 {
+                    break;
+                }
+                allowed = allowed.saturating_sub(sent as usize);
+                if let Some(rate) = pacing_rate {
+                    // credit for at most a tick, we only get to send once per tick
+                    let since = self.next_send.max(now.checked_sub(TICK).unwrap_or(now));
+                    self.next_send = since + Duration::from_secs_f64(sent as f64 / rate);
+                }
+            }
+            self.arm_pto(now);
+        }
+        Ok(())
+    }
+
+    /// Bytes in the network as RFC 6675 counts them, sent and neither
+    /// acknowledged, SACKed nor deemed lost
+    fn pipe(&self) -> usize {
+        self.timer
+            .send_times
+            .iter()
+            .filter(|(_, t)| !t.sacked && !t.lost)
+            .map(|(&seq, t)| t.end.wrapping_sub(seq) as usize)
+            .sum()
+    }
+
+    /// Sends `start..end` again, in as many segments as it takes
+    fn retransmit(&mut self, nic: &mut tun_tap::Iface, start: u32, end: u32) -> Result<(), TcpErr> {
+        let mut seq = start;
+        while wrapping_lt(seq, end) {
+            let sent = self.write(nic, seq, end.wrapping_sub(seq) as usize)?;
+            if sent == 0 {
+                break;
             }
+            seq = seq.wrapping_add(sent);
+        }
+        Ok(())
+    }
+
+    /// Schedules a tail loss probe for what's in flight, RFC 8985 section 7.2
+    fn arm_pto(&mut self, now: Instant) {
+        let in_flight = self.snd.nxt.wrapping_sub(self.snd.una) as usize;
+        if self.timer.pto.is_some() || self.tlp_end.is_some() || self.in_recovery || in_flight == 0
+        {
+            return;
+        }
 
-            self.write(nic, self.snd.nxt, send)?;
+        let mut pto = match self.timer.srtt {
+            // the ACK for a single segment may well be delayed
+            Some(srtt) if in_flight <= self.mss => 2 * srtt + WORST_CASE_DELAYED_ACK,
+            Some(srtt) => 2 * srtt,
+            None => INITIAL_RTO,
+        }
+        .max(MIN_PTO);
+        if let Some(started) = self.timer.rto_started {
+            pto = pto.min((started + self.timer.rto).saturating_duration_since(now));
         }
+        self.timer.pto = Some(now + pto);
+    }
+
+    /// The tail of the flight went unacknowledged, send something that
+    /// makes the peer tell us what it's missing, RFC 8985 section 7.3
+    fn send_probe(&mut self, nic: &mut tun_tap::Iface) -> Result<(), TcpErr> {
+        self.timer.pto = None;
+        let sent_ahead = self.snd.nxt.wrapping_sub(self.snd.una) as usize;
+        let unsent = self.unacked.len().saturating_sub(sent_ahead);
+        let has_new = unsent > 0 || self.closed_at == Some(self.snd.nxt);
+        if has_new && (self.snd.wnd as usize) > sent_ahead {
+            self.write(nic, self.snd.nxt, unsent.min(self.mss))?;
+        } else {
+            let una = self.snd.una;
+            let last = self
+                .timer
+                .send_times
+                .iter()
+                .max_by_key(|(&seq, _)| seq.wrapping_sub(una))
+                .map(|(&seq, t)| (seq, t.end));
+            if let Some((seq, end)) = last {
+                self.write(nic, seq, end.wrapping_sub(seq) as usize)?;
+            }
+        }
+        self.tlp_end = Some(self.snd.nxt);
+        self.timer.rto_started = Some(Instant::now());
         Ok(())
     }
 
+    /// Runs RACK over what's in flight, a loss it finds starts recovery
+    fn detect_loss(&mut self, now: Instant) {
+        let srtt = self.timer.srtt.unwrap_or(INITIAL_RTO);
+        if self
+            .rack
+            .detect_loss(&mut self.timer.send_times, self.in_recovery, srtt, now)
+            && self.may_enter_recovery()
+        {
+            self.enter_recovery();
+        }
+    }
+
+    /// Whether a loss now is a new congestion event, RFC 6582 section 3.2
+    fn may_enter_recovery(&self) -> bool {
+        !self.in_recovery
+            && !matches!(self.recover, Some(recover) if wrapping_lt(self.snd.una, recover))
+    }
+
+    fn enter_recovery(&mut self) {
+        self.in_recovery = true;
+        self.recover = Some(self.snd.nxt);
+        self.congestion
+            .on_loss(self.snd.nxt.wrapping_sub(self.snd.una) as usize);
+    }
+
     pub fn on_packet(
         &mut self,
         nic: &mut tun_tap::Iface,
-        _ip_header: &Ipv4HeaderSlice,
+        ip_header: &Ipv4HeaderSlice,
         tcp_header: &TcpHeaderSlice,
         data: &[u8],
     ) -> Result<Available, TcpErr> {
@@ -167,28 +643,34 @@ impl Connection {
         //     tcp_header.sequence_number(),
         //     tcp_header.acknowledgment_number()
         // );
+        match self.state {
+            State::Closed => {
+                // the connection is gone, it is only kept around for its owner
+                send_rst(nic, ip_header, tcp_header, data)?;
+                return Ok(self.availability());
+            }
+            State::SynSent => return self.on_syn_sent(nic, ip_header, tcp_header, data),
+            _ => {}
+        }
+
+        let ts = timestamp(tcp_header).filter(|_| self.timestamps);
+        if let Some((tsval, _)) = ts {
+            // PAWS, RFC 7323 section 5.3, an older timestamp than the last one
+            // we accepted means an old duplicate from before a sequence number wrap
+            if !tcp_header.rst() && wrapping_lt(tsval, self.ts_recent) {
+                self.write(nic, self.snd.nxt, 0)?;
+                return Ok(self.availability());
+            }
+        }
+
         // A segment is judged to occupy a portion of valid receive sequence
         // space if
         //    RCV.NXT =< SEG.SEQ < RCV.NXT+RCV.WND
         // or
         //    RCV.NXT =< SEG.SEQ+SEG.LEN-1 < RCV.NXT+RCV.WND
         let seqn = tcp_header.sequence_number();
-        let slen = {
-            // SEG.LEN = the number of octets occupied by the data in the segment
-            // (counting SYN and FIN)
-
-            let mut len = data.len() as u32;
-            if tcp_header.syn() {
-                len += 1;
-            }
-
-            if tcp_header.fin() {
-                len += 1;
-            }
-
-            len
-        };
-        let wend = self.rcv.nxt.wrapping_add(self.rcv.wnd as u32);
+        let slen = segment_len(tcp_header, data);
+        let wend = self.rcv.nxt.wrapping_add(self.rcv.wnd);
         let okay = if slen == 0 {
             if self.rcv.wnd == 0 {
                 seqn == self.rcv.nxt
@@ -207,7 +689,39 @@ impl Connection {
         };
 
         if !okay {
-            self.write(nic, self.snd.nxt, 0)?;
+            // unacceptable segments are ACKed, unless they are resets
+            if !tcp_header.rst() {
+                self.write(nic, self.snd.nxt, 0)?;
+            }
+            return Ok(self.availability());
+        }
+
+        if let Some((tsval, _)) = ts {
+            // only remember timestamps of segments that don't skip ahead of what
+            // we've acked, so TS.Recent stays the one the peer will echo next
+            if !wrapping_lt(tsval, self.ts_recent) && !wrapping_lt(self.last_ack_sent, seqn) {
+                self.ts_recent = tsval;
+            }
+        }
+
+        // the peer is still there
+        self.timer.last_received = Instant::now();
+        self.timer.keepalive_probes = 0;
+
+        if tcp_header.rst() {
+            // RFC 5961, only a reset exactly at RCV.NXT is believed, anything
+            // else in the window could be blind injection and gets a challenge ACK
+            if seqn != self.rcv.nxt {
+                self.write(nic, self.snd.nxt, 0)?;
+                return Ok(self.availability());
+            }
+
+            match self.state {
+                State::Closing | State::LastAck | State::TimeWait => {
+                    self.state = State::Closed;
+                }
+                _ => self.abort(ErrorKind::ConnectionReset),
+            }
             return Ok(self.availability());
         }
 
@@ -236,107 +750,367 @@ impl Connection {
                 self.snd.nxt.wrapping_add(1),
             ) {
                 self.state = State::Estab;
+                self.snd.wnd = (tcp_header.window_size() as u32) << self.snd.wscale;
+                self.snd.wl1 = seqn;
+                self.snd.wl2 = ackn;
             } else {
-                //TODO  <SEQ=SEG.ACK><CTL=RST>
+                // <SEQ=SEG.ACK><CTL=RST>
+                send_rst(nic, ip_header, tcp_header, data)?;
+                return Ok(self.availability());
             }
         }
 
-        if State::Estab == self.state
-            || State::FinWait1 == self.state
-            || State::FinWait2 == self.state
+        if let State::Estab
+        | State::FinWait1
+        | State::FinWait2
+        | State::CloseWait
+        | State::Closing
+        | State::LastAck = self.state
         {
             // If SND.UNA < SEG.ACK =< SND.NXT then, set SND.UNA <- SEG.ACK.
             if is_between_wrapping(self.snd.una, ackn, self.snd.nxt.wrapping_add(1)) {
+                let acked = ackn.wrapping_sub(self.snd.una) as usize;
                 if !self.unacked.is_empty() {
-                    let _ = self
-                        .unacked
-                        .drain(..ackn.wrapping_sub(self.snd.una) as usize)
-                        .count();
-                    self.timer.send_times.retain(|&seq, sent| {
-                        if is_between_wrapping(self.snd.una, seq, ackn) {
-                            let srtt = self.timer.srtt.as_secs_f64();
-                            self.timer.srtt = Duration::from_secs_f64(
-                                0.8 * srtt + (1.0 - 0.8) * sent.elapsed().as_secs_f64(),
-                            );
-                            false
-                        } else {
-                            true
-                        }
-                    });
+                    let mut acked = acked;
+                    // the SYN and FIN take up sequence space but are not in `unacked`
+                    if self.snd.una == self.snd.iss {
+                        acked -= 1;
+                    }
+                    if matches!(self.closed_at, Some(fin) if wrapping_lt(fin, ackn)) {
+                        acked -= 1;
+                    }
+                    let _ = self.unacked.drain(..acked.min(self.unacked.len())).count();
                 }
 
+                let mut timed = None;
+                let mut ambiguous = false;
+                let now = Instant::now();
+                self.timer.send_times.retain(|&seq, t| {
+                    // SND.UNA =< SEQ < SEG.ACK
+                    if !is_between_wrapping(self.snd.una.wrapping_sub(1), seq, ackn) {
+                        return true;
+                    }
+                    if t.retransmitted {
+                        ambiguous = true;
+                    } else {
+                        timed = Some(now - t.at);
+                    }
+                    if !t.sacked {
+                        self.rack.on_delivered(t, now);
+                    }
+                    false
+                });
+                // Karn's algorithm, an ACK covering a retransmission can't be
+                // timed, but the echoed timestamp tells the copies apart
+                let timed = timed.filter(|_| !ambiguous);
+                let echoed = self.echoed_rtt(tcp_header);
+                if let Some(rtt) = echoed.or(timed) {
+                    self.timer.on_rtt_sample(rtt);
+                }
+                let rate = self.rate.on_ack(self.snd.una, ackn);
+                self.congestion.on_ack(Ack {
+                    acked,
+                    in_flight: self.snd.nxt.wrapping_sub(ackn) as usize,
+                    snd_una: ackn,
+                    snd_nxt: self.snd.nxt,
+                    // timestamps only tick in milliseconds, too coarse for
+                    // a model of the path
+                    rtt: timed.or(echoed),
+                    delivered: self.rate.delivered(),
+                    rate,
+                    in_recovery: self.in_recovery,
+                });
+
+                self.scoreboard.advance(self.snd.una, ackn);
                 self.snd.una = ackn;
+                self.timer.on_ack(ackn == self.snd.nxt);
+                self.timer.pto = None;
+                if self.tlp_end.is_some_and(|end| !wrapping_lt(ackn, end)) {
+                    self.tlp_end = None;
+                }
+
+                self.dupacks = 0;
+                let inflation = std::mem::take(&mut self.inflation);
+                if self.in_recovery {
+                    if matches!(self.recover, Some(recover) if wrapping_lt(ackn, recover)) {
+                        // a partial ACK, the next hole is right behind it, RFC 6582 section 3.2
+                        self.inflation = inflation.saturating_sub(acked) + self.mss;
+                        self.write(nic, self.snd.una, self.mss)?;
+                    } else {
+                        self.in_recovery = false;
+                    }
+                }
+            } else if ackn == self.snd.una
+                && self.snd.nxt != self.snd.una
+                && data.is_empty()
+                && !tcp_header.syn()
+                && !tcp_header.fin()
+                && (tcp_header.window_size() as u32) << self.snd.wscale == self.snd.wnd
+            {
+                self.on_dup_ack(nic)?;
+            }
+
+            // SND.UNA =< SEG.ACK =< SND.NXT, and the segment isn't older than
+            // the one we last took the window from
+            if !wrapping_lt(ackn, self.snd.una)
+                && !wrapping_lt(self.snd.nxt, ackn)
+                && (wrapping_lt(self.snd.wl1, seqn)
+                    || (self.snd.wl1 == seqn && !wrapping_lt(ackn, self.snd.wl2)))
+            {
+                self.snd.wnd = (tcp_header.window_size() as u32) << self.snd.wscale;
+                self.snd.wl1 = seqn;
+                self.snd.wl2 = ackn;
             }
+
+            if self.sack_permitted {
+                for option in options(tcp_header) {
+                    if let TcpOptionElement::SelectiveAcknowledgement(first, rest) = option {
+                        for (left, right) in
+                            std::iter::once(first).chain(rest.into_iter().flatten())
+                        {
+                            self.scoreboard
+                                .insert(self.snd.una, self.snd.nxt, left, right);
+                        }
+                    }
+                }
+
+                let now = Instant::now();
+                for (&seq, t) in self.timer.send_times.iter_mut() {
+                    if !t.sacked && self.scoreboard.covers(self.snd.una, seq, t.end) {
+                        t.sacked = true;
+                        t.lost = false;
+                        self.rack.on_delivered(t, now);
+                    }
+                }
+            }
+            self.detect_loss(Instant::now());
         }
 
-        if State::FinWait1 == self.state {
-            // check the syn and the fin we just send has been acked
-            if self.snd.una == self.snd.iss + 2 {
-                eprintln!("THEY'VE ACKED OUR FIN");
-                self.state = State::FinWait2;
+        if self.is_fin_acked() {
+            match self.state {
+                State::FinWait1 => {
+                    eprintln!("THEY'VE ACKED OUR FIN");
+                    self.state = State::FinWait2;
+                }
+                State::Closing => self.enter_time_wait(),
+                State::LastAck => {
+                    self.state = State::Closed;
+                    return Ok(self.availability());
+                }
+                _ => {}
             }
         }
 
+        // ACKed right away unless it's in order data the ACK may wait for
+        let mut quick = true;
+        // segment text is only accepted until the peer has sent its FIN
         if State::Estab == self.state
             || State::FinWait1 == self.state
             || State::FinWait2 == self.state
         {
-            let mut unread_data_at = (self.rcv.nxt - seqn) as usize;
-            if unread_data_at > data.len() {
-                // we must have received a re-transmitted FIN that we have already
-                // seen nxt points to beyond the fin, but the fin is not in data
-                assert_eq!(unread_data_at, data.len() + 1);
-                unread_data_at = 0;
-            }
-            //TODO only read stfuf we haven't read
-            //Would index out of range
-            self.incoming.extend(&data[unread_data_at..]);
-
-            // Once the TCP takes responsibility for the data it advances
-            // RCV.NXT over the data accepted, and adjusts RCV.WND as
-            // apporopriate to the current buffer availability.  The total of
-            // RCV.NXT and RCV.WND should not be reduced
-            self.rcv.nxt = seqn.wrapping_add({
-                let mut len = data.len() as u32;
-                if tcp_header.fin() {
-                    len += 1;
+            // only keep what fits the window we offered, the peer sends the rest again
+            let wend = self.rcv.nxt.wrapping_add(self.rcv.wnd);
+            let room = wend.wrapping_sub(seqn) as usize;
+            let (data, seg_fin) = if data.len() > room {
+                (&data[..room], false)
+            } else {
+                (data, tcp_header.fin())
+            };
+
+            if !data.is_empty() {
+                if let Some(rtt) = self.echoed_rtt(tcp_header) {
+                    self.rcv_space.on_rtt_sample(rtt);
                 }
+            }
+
+            let nxt = self.rcv.nxt;
+            let had_gap = self.reassembly.buffered() > 0;
+            if wrapping_lt(nxt, seqn) {
+                // the sender may be recovering from a loss, don't slow it down
+                self.quickacks = MAX_QUICKACKS;
+            }
 
-                len
-            });
+            let mut fin = false;
+            if wrapping_lt(self.rcv.nxt, seqn) {
+                // there's a gap in front of this segment, hold on to it until it's filled
+                self.reassembly.insert(self.rcv.nxt, seqn, data, seg_fin);
+            } else {
+                // only take the part of the segment we haven't seen yet
+                let unread_data_at = self.rcv.nxt.wrapping_sub(seqn) as usize;
+                let seg_end = seqn.wrapping_add(data.len() as u32);
+                if unread_data_at < data.len() {
+                    self.incoming.extend(&data[unread_data_at..]);
+                    // Once the TCP takes responsibility for the data it advances
+                    // RCV.NXT over the data accepted, and adjusts RCV.WND as
+                    // apporopriate to the current buffer availability.  The total of
+                    // RCV.NXT and RCV.WND should not be reduced
+                    self.rcv.nxt = seg_end;
+                }
+                // a re-transmitted FIN we've already seen ends before nxt
+                fin = seg_fin && self.rcv.nxt == seg_end;
+            }
 
-            // Send an acknowledgment of the form:
-            //<SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
-            self.write(nic, self.snd.nxt, 0)?;
-            //TODO: wake up waiting readers
+            let (ready, queued_fin) = self.reassembly.pop(self.rcv.nxt);
+            self.rcv.nxt = self.rcv.nxt.wrapping_add(ready.len() as u32);
+            self.incoming.extend(ready);
+            fin |= queued_fin;
+            if fin {
+                self.rcv.nxt = self.rcv.nxt.wrapping_add(1);
+            }
+            // RFC 5681 section 4.2, out of order data and what fills a gap is
+            // acked right away so the sender learns about the loss, and so
+            // is a duplicate in case our ACK got lost
+            quick = fin || had_gap || self.rcv.nxt == nxt;
+            // the buffer filled by what we took, the right edge stays put
+            self.rcv.wnd = if wrapping_lt(self.rcv.nxt, wend) {
+                wend.wrapping_sub(self.rcv.nxt)
+            } else {
+                0
+            };
+
+            if fin {
+                match self.state {
+                    State::Estab => self.state = State::CloseWait,
+                    // our FIN would have moved us to FIN-WAIT-2 above if it was acked
+                    State::FinWait1 => self.state = State::Closing,
+                    State::FinWait2 => self.enter_time_wait(),
+                    _ => unreachable!(),
+                }
+            }
+        } else if State::TimeWait == self.state && tcp_header.fin() {
+            // the peer didn't get our ACK of its FIN, restart the 2 MSL timeout
+            self.enter_time_wait();
         }
 
-        if tcp_header.fin() {
-            match self.state {
-                State::FinWait2 => {
-                    eprintln!("THEY'VE FINED");
-                    self.write(nic, self.snd.nxt, 0)?;
-                    self.state = State::TimeWait;
+        if slen > 0 {
+            self.delayed_segments += 1;
+            // at least every second segment is acked, RFC 5681 section 4.2,
+            // and every one in quick-ack mode while the sender's slow start
+            // needs the ACKs to grow its window
+            if quick || self.delayed_segments >= 2 || self.quickacks > 0 || self.ack_delay.is_none()
+            {
+                self.quickacks = self.quickacks.saturating_sub(1);
+                // Send an acknowledgment of the form:
+                //<SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
+                // this also re-acks a retransmitted FIN in the states past it
+                self.write(nic, self.snd.nxt, 0)?;
+            } else if self.timer.delayed_ack.is_none() {
+                // data we send before then carries the ACK
+                self.timer.delayed_ack = self.ack_delay.map(|delay| Instant::now() + delay);
+            }
+        }
+
+        Ok(self.availability())
+    }
+
+    fn on_syn_sent(
+        &mut self,
+        nic: &mut tun_tap::Iface,
+        ip_header: &Ipv4HeaderSlice,
+        tcp_header: &TcpHeaderSlice,
+        data: &[u8],
+    ) -> Result<Available, TcpErr> {
+        let ackn = tcp_header.acknowledgment_number();
+        // If SEG.ACK =< ISS or SEG.ACK > SND.NXT the ACK is unacceptable
+        if tcp_header.ack()
+            && !is_between_wrapping(self.snd.iss, ackn, self.snd.nxt.wrapping_add(1))
+        {
+            // <SEQ=SEG.ACK><CTL=RST>
+            send_rst(nic, ip_header, tcp_header, data)?;
+            return Ok(self.availability());
+        }
+
+        if tcp_header.rst() {
+            // without an ACK we can't tell whether the reset is meant for our SYN
+            if tcp_header.ack() {
+                self.abort(ErrorKind::ConnectionRefused);
+            }
+            return Ok(self.availability());
+        }
+
+        if !tcp_header.syn() {
+            return Ok(self.availability());
+        }
+
+        // RCV.NXT is set to SEG.SEQ+1, IRS is set to SEG.SEQ
+        let seqn = tcp_header.sequence_number();
+        self.rcv.irs = seqn;
+        self.rcv.nxt = seqn.wrapping_add(1);
+        // the window in a SYN is never scaled
+        self.snd.wnd = tcp_header.window_size() as u32;
+        self.snd.wl1 = seqn;
+        self.snd.wl2 = ackn;
+        let syn = SynOptions::from_header(tcp_header);
+        self.mss = syn.mss.min(self.mss);
+        self.congestion = self.congestion_algorithm.build(self.mss);
+        self.tcp.ack = true;
+        self.sack_permitted &= syn.sack_permitted;
+        match timestamp(tcp_header) {
+            Some((tsval, _)) if self.timestamps => {
+                self.ts_recent = tsval;
+                if let Some(rtt) = self.echoed_rtt(tcp_header) {
+                    self.timer.on_rtt_sample(rtt);
                 }
-                _ => unimplemented!(),
             }
+            _ => self.timestamps = false,
+        }
+        match syn.wscale {
+            Some(shift) if self.window_scaling => self.snd.wscale = shift,
+            _ => {
+                self.window_scaling = false;
+                self.rcv.wscale = 0;
+                self.rcv.wnd = self.rcv.wnd.min(u16::MAX as u32);
+            }
+        }
+
+        if tcp_header.ack() {
+            // our SYN has been ACKed, <SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
+            self.snd.una = ackn;
+            // our SYN was all that was in flight
+            self.timer.send_times.clear();
+            self.timer.on_ack(ackn == self.snd.nxt);
+            self.state = State::Estab;
+            self.write(nic, self.snd.nxt, 0)?;
+        } else {
+            // simultaneous open, <SEQ=ISS><ACK=RCV.NXT><CTL=SYN,ACK>
+            self.state = State::SynRcvd;
+            self.tcp.syn = true;
+            self.write(nic, self.snd.iss, 0)?;
         }
 
         Ok(self.availability())
     }
 
+    /// Payload of a full sized segment, leaving room for the timestamps every
+    /// segment carries
+    fn full_segment(&self) -> usize {
+        // TSopt with the padding in front of it
+        let options = if self.timestamps { 12 } else { 0 };
+        self.mss.saturating_sub(options)
+    }
+
+    /// Sends one segment starting at `seq` with at most `limit` bytes of
+    /// payload, returns how much sequence space it took up. Our FIN rides
+    /// along once the payload reaches it.
     fn write(&mut self, nic: &mut tun_tap::Iface, seq: u32, limit: usize) -> Result<u32, TcpErr> {
-        let mut buffer = [0u8; 1500];
+        let mut buffer = vec![0u8; self.mtu];
         self.tcp.sequence_number = seq;
         self.tcp.acknowledgment_number = self.rcv.nxt;
-
-        let mut offset = seq.wrapping_sub(self.snd.una) as usize;
-        // we need to special-case the two "virtual" bytes SYN and FIN
-        if let Some(closed_at) = self.closed_at {
-            if seq == closed_at.wrapping_add(1) {
-                offset = 0;
-            }
+        // the window in a SYN is never scaled
+        let shift = if self.tcp.syn { 0 } else { self.rcv.wscale };
+        self.tcp.window_size = (self.rcv.wnd >> shift).min(u16::MAX as u32) as u16;
+        let options = self.options();
+        self.tcp.set_options(&options)?;
+        if self.tcp.ack {
+            self.last_ack_sent = self.rcv.nxt;
+            // every ACK carries the window
+            self.window_update = false;
+            self.timer.delayed_ack = None;
+            self.delayed_segments = 0;
         }
+
+        // SYN and FIN take up sequence space but are not in `unacked`
+        let offset = (seq.wrapping_sub(self.snd.una) as usize).min(self.unacked.len());
         let (mut payload1, mut payload2) = self.unacked.as_slices();
         if payload1.len() >= offset {
             payload1 = &payload1[offset..];
@@ -346,23 +1120,15 @@ impl Connection {
             payload2 = &payload2[(offset - skipped)..];
         }
 
-        let size = buffer
-            .len()
-            .min(self.tcp.header_len() as usize + self.ip.header_len() + limit);
-        self.ip.set_payload_len(size - self.ip.header_len())?;
-
-        self.tcp.checksum = self
-            .tcp
-            .calc_checksum_ipv4(&self.ip, &[])
-            .expect("failed to compute checksum");
-
         use std::io::Write;
-        let mut unwritten = &mut buffer[..];
-        self.ip.write(&mut unwritten)?;
-        self.tcp.write(&mut unwritten)?;
+        // the checksum covers the payload, so lay it down behind the headers first
+        let header_len = self.ip.header_len() + self.tcp.header_len() as usize;
+        // options come out of the MSS, RFC 6691
+        let room = self.mss.saturating_sub(header_len - HEADERS_LEN);
         let payload_bytes = {
+            let mut unwritten = &mut buffer[header_len..];
             let mut written = 0;
-            let mut limit = limit.min(payload1.len() + payload2.len());
+            let mut limit = limit.min(room).min(payload1.len() + payload2.len());
 
             let p1l = limit.min(payload1.len());
             written += unwritten.write(&payload1[..p1l])?;
@@ -373,9 +1139,23 @@ impl Connection {
 
             written
         };
-        let unwritten = unwritten.len();
+        if self.closed_at == Some(seq.wrapping_add(payload_bytes as u32)) {
+            self.tcp.fin = true;
+        }
+        let size = header_len + payload_bytes;
+        self.ip.set_payload_len(size - self.ip.header_len())?;
+
+        self.tcp.checksum = self
+            .tcp
+            .calc_checksum_ipv4(&self.ip, &buffer[header_len..size])
+            .expect("failed to compute checksum");
+
+        let mut unwritten = &mut buffer[..header_len];
+        self.ip.write(&mut unwritten)?;
+        self.tcp.write(&mut unwritten)?;
 
-        //self.snd.nxt = self.snd.nxt.wrapping_add(payload_bytes);
+        let retransmission = wrapping_lt(seq, self.snd.nxt);
+        let idle = self.snd.nxt == self.snd.una;
         let mut next_seq = seq.wrapping_add(payload_bytes as u32);
         if self.tcp.syn {
             next_seq = next_seq.wrapping_add(1);
@@ -391,56 +1171,203 @@ impl Connection {
             self.snd.nxt = next_seq;
         }
 
-        self.timer.send_times.insert(seq, Instant::now());
-        nic.send(&buffer[..buffer.len() - unwritten])?;
-        Ok(payload_bytes as u32)
-    }
-
-    fn send_rst(&mut self, nic: &mut tun_tap::Iface) -> Result<(), TcpErr> {
-        self.tcp.rst = true;
-        // TODO: fix sequence number
-        // If the incoming segment has an ACK field, the reset takes its
-        // sequence number from the ACK field of the segment, otherwise the
-        // reset has sequence number zero and the ACK field is set to the sum
-        // of the sequence number and segment length of the incoming segment.
-        // The connection remains in the CLOSED state.
-
-        // TODO: handle synchronized RST
-        // 3.  If the connection is in a synchronized state (ESTABLISHED,
-        //     FIN-WAIT-1, FIN-WAIT-2, CLOSE-WAIT, CLOSING, LAST-ACK, TIME-WAIT),
-        //     any unacceptable segment (out of window sequence number or
-        //     unacceptible acknowledgment number) must elicit only an empty
-        //     acknowledgment segment containing the current send-sequence number
-        //     and an acknowledgment indicating the next sequence number expected
-        //     to be received, and the connection remains in the same state.
-
-        self.tcp.sequence_number = 0;
-        self.tcp.acknowledgment_number = 0;
-        self.write(nic, self.snd.nxt, 0)?;
-        Ok(())
+        // pure ACKs are never acknowledged, so there's nothing to time
+        if next_seq != seq {
+            self.timer.on_send(seq, next_seq, retransmission);
+            self.rate.on_send(seq, idle);
+        }
+        nic.send(&buffer[..size])?;
+        Ok(next_seq.wrapping_sub(seq))
     }
 
+    /// The peer has sent its FIN, there won't be any more data
     pub(crate) fn is_rcv_closed(&self) -> bool {
-        self.state == State::TimeWait
+        matches!(
+            self.state,
+            State::CloseWait | State::Closing | State::LastAck | State::TimeWait | State::Closed
+        )
+    }
+
+    /// Options for the segment `write` is about to send
+    fn options(&self) -> Vec<TcpOptionElement> {
+        use TcpOptionElement::*;
+
+        let mut options = Vec::new();
+        if self.timestamps {
+            options.extend([Noop, Noop, Timestamp(self.timer.ts_now(), self.ts_recent)]);
+        }
+        if self.tcp.syn {
+            let wscale = self.window_scaling.then_some(self.rcv.wscale);
+            options.extend(syn_options(self.mtu, self.sack_permitted, wscale));
+        } else if self.sack_permitted {
+            let mut blocks = self.reassembly.blocks().into_iter();
+            if let Some(first) = blocks.next() {
+                // only three blocks fit next to the timestamps
+                let mut rest = [None; 3];
+                let room = if self.timestamps { 2 } else { 3 };
+                for (slot, block) in rest[..room].iter_mut().zip(blocks) {
+                    *slot = Some(block);
+                }
+                options.extend([Noop, Noop, SelectiveAcknowledgement(first, rest)]);
+            }
+        }
+        options
+    }
+
+    /// RFC 5681 section 3.2 with the NewReno modification of RFC 6582, and
+    /// Limited Transmit (RFC 3042) for the duplicate ACKs before that
+    fn on_dup_ack(&mut self, nic: &mut tun_tap::Iface) -> Result<(), TcpErr> {
+        self.dupacks += 1;
+        if self.in_recovery {
+            // another segment has left the network
+            self.inflation += self.mss;
+            return Ok(());
+        }
+        if self.dupacks < DUPACK_THRESHOLD {
+            // one new segment for each of the first two, to get more of them going
+            self.inflation = self.dupacks as usize * self.mss;
+            return Ok(());
+        }
+        self.inflation = 0;
+        if self.dupacks > DUPACK_THRESHOLD || !self.may_enter_recovery() {
+            // the loss was already dealt with by an earlier recovery or timeout
+            return Ok(());
+        }
+
+        self.enter_recovery();
+        self.inflation = DUPACK_THRESHOLD as usize * self.mss;
+        self.write(nic, self.snd.una, self.mss)?;
+        Ok(())
+    }
+
+    /// How long ago we sent the timestamp the peer echoes in `tcp_header`
+    fn echoed_rtt(&self, tcp_header: &TcpHeaderSlice) -> Option<Duration> {
+        match timestamp(tcp_header) {
+            Some((_, tsecr)) if self.timestamps && tsecr != 0 => Some(Duration::from_millis(
+                self.timer.ts_now().wrapping_sub(tsecr) as u64,
+            )),
+            _ => None,
+        }
+    }
+
+    /// The connection is gone, blocked and future callers see `kind`
+    fn abort(&mut self, kind: ErrorKind) {
+        self.state = State::Closed;
+        self.error = Some(kind);
+        self.incoming.clear();
+        self.unacked.clear();
+    }
+
+    fn enter_time_wait(&mut self) {
+        self.state = State::TimeWait;
+        self.timer.time_wait = Some(Instant::now());
+    }
+
+    /// Nobody holds this connection any more and it has finished closing,
+    /// TIME-WAIT ends after twice the maximum segment lifetime. A peer that
+    /// has our FIN but stays quiet past `fin_timeout` isn't waited for.
+    pub(crate) fn is_reapable(&self, config: &Config) -> bool {
+        if !self.detached {
+            return false;
+        }
+
+        match self.state {
+            State::Closed => true,
+            // nothing is in flight, no other timer would ever end it
+            State::FinWait2 => self.timer.last_received.elapsed() >= config.fin_timeout,
+            State::TimeWait => match (self.timer.time_wait, config.msl.checked_mul(2)) {
+                (Some(since), Some(time_wait)) => since.elapsed() >= time_wait,
+                // a lifetime too long to double never ends
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn is_fin_acked(&self) -> bool {
+        matches!(self.closed_at, Some(fin) if wrapping_lt(fin, self.snd.una))
     }
 
     fn availability(&self) -> Available {
         let mut a = Available::empty();
 
+        if self.error.is_some() {
+            return Available::Read | Available::Write;
+        }
+
         if self.is_rcv_closed() || !self.incoming.is_empty() {
             a |= Available::Read
         }
 
+        if State::Estab == self.state || State::CloseWait == self.state {
+            a |= Available::Write
+        }
+
         a
     }
 
+    /// Hands congestion control to a fresh `algorithm` controller
+    pub(crate) fn set_congestion_control(&mut self, algorithm: CongestionAlgorithm) {
+        self.congestion_algorithm = algorithm;
+        self.congestion = algorithm.build(self.mss);
+    }
+
+    /// The application read `n` bytes, returns the receive buffer size it
+    /// would take to keep up with its reads when that's more than we have
+    pub(crate) fn on_read(&mut self, n: usize) -> Option<usize> {
+        self.open_window();
+        self.rcv_space
+            .on_read(n, self.timer.srtt, Instant::now())
+            .filter(|&wanted| wanted > self.rcv_buffer)
+    }
+
+    pub(crate) fn rcv_buffer(&self) -> usize {
+        self.rcv_buffer
+    }
+
+    /// Grows the receive buffer to `size`, auto-tuning never shrinks it
+    pub(crate) fn grow_rcv_buffer(&mut self, size: usize) {
+        self.rcv_buffer = self.rcv_buffer.max(size.min(MAX_WINDOW));
+        self.open_window();
+    }
+
+    /// Opens the receive window over the free buffer space, only once it can
+    /// grow by a sizeable amount to avoid silly window syndrome,
+    /// RFC 9293 section 3.8.6.2.2
+    fn open_window(&mut self) {
+        // out of order data takes up room in the buffer as well
+        let used = self.incoming.len() + self.reassembly.buffered();
+        let largest = (u16::MAX as usize) << self.rcv.wscale;
+        let free = self.rcv_buffer.saturating_sub(used).min(largest) as u32;
+        let threshold = (self.rcv_buffer / 2).min(self.mtu - HEADERS_LEN) as u32;
+        if free >= self.rcv.wnd.saturating_add(threshold) {
+            self.rcv.wnd = free;
+            self.window_update = true;
+        }
+    }
+
+    pub(crate) fn check_error(&self) -> io::Result<()> {
+        match self.error {
+            Some(kind) => Err(kind.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Aborts the connection, the peer is told with a reset from the next tick
+    pub(crate) fn reset(&mut self) {
+        self.reset = true;
+    }
+
     pub fn close(&mut self) -> Result<(), std::io::Error> {
         self.closed = true;
         match self.state {
             State::Estab | State::SynRcvd => {
                 self.state = State::FinWait1;
             }
-            State::FinWait1 | State::FinWait2 => {}
+            State::CloseWait => {
+                self.state = State::LastAck;
+            }
+            State::FinWait1 | State::FinWait2 | State::Closing | State::LastAck => {}
             _ => {
                 return Err(std::io::Error::new(
                     ErrorKind::NotConnected,
@@ -448,10 +1375,179 @@ impl Connection {
                 ));
             }
         }
+        if self.closed_at.is_none() {
+            // the FIN goes right behind everything written so far, which
+            // sits behind our SYN for as long as that isn't acked
+            let syn = (self.snd.una == self.snd.iss) as u32;
+            self.closed_at = Some(
+                self.snd
+                    .una
+                    .wrapping_add(syn)
+                    .wrapping_add(self.unacked.len() as u32),
+            );
+        }
         Ok(())
     }
 }
 
+/// Answers a SYN without keeping any state, the SYN cookie in the ISS tells
+/// us what we need to know once the handshake completes
+pub fn send_syn_cookie(
+    nic: &mut tun_tap::Iface,
+    ip_header: &Ipv4HeaderSlice,
+    tcp_header: &TcpHeaderSlice,
+    quad: &Quad,
+    cookies: &mut SynCookies,
+    config: &Config,
+) -> Result<(), TcpErr> {
+    use TcpOptionElement::*;
+
+    let syn = SynOptions::from_header(tcp_header);
+    let irs = tcp_header.sequence_number();
+    let iss = cookies.encode(quad, irs, &syn);
+    let (rcv_buffer, rcv_shift) = rcv_buffer_and_shift(config);
+
+    // the window in a SYN is never scaled
+    let mut tcp = TcpHeader::new(
+        tcp_header.destination_port(),
+        tcp_header.source_port(),
+        iss,
+        rcv_buffer.min(u16::MAX as usize) as u16,
+    );
+    tcp.syn = true;
+    tcp.ack = true;
+    tcp.acknowledgment_number = irs.wrapping_add(1);
+
+    let mut options = Vec::new();
+    if let Some((tsval, _)) = timestamp(tcp_header) {
+        // the connection made from the cookie keeps this clock, so the
+        // TSecr of the ACK gives a real RTT
+        options.extend([Noop, Noop, Timestamp(ts_since(cookies.ts_origin()), tsval)]);
+    }
+    options.extend(syn_options(
+        config.mtu,
+        syn.sack_permitted,
+        syn.wscale.map(|_| rcv_shift),
+    ));
+    tcp.set_options(&options)?;
+
+    send_reply(nic, ip_header, tcp)
+}
+
+/// The options only a SYN carries, besides the timestamps
+fn syn_options(mtu: usize, sack_permitted: bool, wscale: Option<u8>) -> Vec<TcpOptionElement> {
+    use TcpOptionElement::*;
+
+    let mut options = vec![MaximumSegmentSize((mtu - HEADERS_LEN) as u16)];
+    if sack_permitted {
+        options.extend([Noop, Noop, SelectiveAcknowledgementPermitted]);
+    }
+    if let Some(shift) = wscale {
+        options.extend([Noop, WindowScale(shift)]);
+    }
+    options
+}
+
+/// The MSS the peer's SYN announces
+fn peer_mss(tcp_header: &TcpHeaderSlice) -> usize {
+    options(tcp_header)
+        .find_map(|o| match o {
+            TcpOptionElement::MaximumSegmentSize(mss) => Some((mss as usize).max(MIN_MSS)),
+            _ => None,
+        })
+        .unwrap_or(DEFAULT_MSS)
+}
+
+/// The options of a segment we understand, malformed or unknown ones are skipped
+fn options<'a>(tcp_header: &'a TcpHeaderSlice<'a>) -> impl Iterator<Item = TcpOptionElement> + 'a {
+    tcp_header.options_iterator().filter_map(Result::ok)
+}
+
+/// TSval and TSecr of the segment's Timestamps option
+fn timestamp(tcp_header: &TcpHeaderSlice) -> Option<(u32, u32)> {
+    options(tcp_header).find_map(|o| match o {
+        TcpOptionElement::Timestamp(tsval, tsecr) => Some((tsval, tsecr)),
+        _ => None,
+    })
+}
+
+/// SEG.LEN, the number of octets occupied by the data in the segment
+/// (counting SYN and FIN)
+fn segment_len(tcp_header: &TcpHeaderSlice, data: &[u8]) -> u32 {
+    let mut len = data.len() as u32;
+    if tcp_header.syn() {
+        len += 1;
+    }
+
+    if tcp_header.fin() {
+        len += 1;
+    }
+
+    len
+}
+
+/// Answers a segment with a reset, leaving any connection state alone.
+///
+/// If the incoming segment has an ACK field, the reset takes its
+/// sequence number from the ACK field of the segment, otherwise the
+/// reset has sequence number zero and the ACK field is set to the sum
+/// of the sequence number and segment length of the incoming segment.
+pub fn send_rst(
+    nic: &mut tun_tap::Iface,
+    ip_header: &Ipv4HeaderSlice,
+    tcp_header: &TcpHeaderSlice,
+    data: &[u8],
+) -> Result<(), TcpErr> {
+    // a reset is never answered with another reset
+    if tcp_header.rst() {
+        return Ok(());
+    }
+
+    let mut tcp = TcpHeader::new(
+        tcp_header.destination_port(),
+        tcp_header.source_port(),
+        0,
+        0,
+    );
+    tcp.rst = true;
+    if tcp_header.ack() {
+        tcp.sequence_number = tcp_header.acknowledgment_number();
+    } else {
+        tcp.ack = true;
+        tcp.acknowledgment_number = tcp_header
+            .sequence_number()
+            .wrapping_add(segment_len(tcp_header, data));
+    }
+
+    send_reply(nic, ip_header, tcp)
+}
+
+/// Sends `tcp` without payload back to where `ip_header` came from
+fn send_reply(
+    nic: &mut tun_tap::Iface,
+    ip_header: &Ipv4HeaderSlice,
+    mut tcp: TcpHeader,
+) -> Result<(), TcpErr> {
+    let ip = Ipv4Header::new(
+        tcp.header_len(),
+        64,
+        ip_number::TCP,
+        ip_header.destination(),
+        ip_header.source(),
+    );
+    tcp.checksum = tcp
+        .calc_checksum_ipv4(&ip, &[])
+        .expect("failed to compute checksum");
+
+    let mut buffer = [0u8; 80];
+    let size = ip.header_len() + tcp.header_len() as usize;
+    let mut unwritten = &mut buffer[..size];
+    ip.write(&mut unwritten)?;
+    tcp.write(&mut unwritten)?;
+    nic.send(&buffer[..size])?;
+    Ok(())
+}
+
 // I am not quiet understanding this version, but the streaming use this So I keep with it
 fn wrapping_lt(lhs: u32, rhs: u32) -> bool {
     // From RFC1323:
@@ -539,10 +1635,13 @@ fn is_between_wrapping(start: u32, x: u32, end: u32) -> bool {
 pub struct SendSeuquenceSpace {
     una: u32,
     nxt: u32,
-    wnd: u16,
+    wnd: u32,
+    /// Shift count the peer applies to the windows it advertises
+    wscale: u8,
+    #[allow(dead_code)]
     up: u16,
-    wl1: usize,
-    wl2: usize,
+    wl1: u32,
+    wl2: u32,
     iss: u32,
 }
 
@@ -563,7 +1662,10 @@ pub struct SendSeuquenceSpace {
 #[derive(Debug)]
 pub struct ReceiveSequenceSpace {
     nxt: u32,
-    wnd: u16,
+    wnd: u32,
+    /// Shift count we apply to the windows we advertise
+    wscale: u8,
+    #[allow(dead_code)]
     up: u16,
     irs: u32,
 }
@@ -577,12 +1679,194 @@ pub struct Connection {
 
     timer: Timer,
     pub(crate) incoming: VecDeque<u8>,
+    /// Data received ahead of `rcv.nxt`
+    reassembly: Reassembly,
     pub(crate) unacked: VecDeque<u8>,
     pub(crate) closed: bool,
+    /// Sequence number of our FIN, known as soon as we close
     closed_at: Option<u32>,
+    /// No `TcpStream` or `TcpListener` refers to this connection anymore
+    pub(crate) detached: bool,
+    /// Why the connection was aborted
+    pub(crate) error: Option<ErrorKind>,
+    /// Both sides agreed to use selective acknowledgments
+    sack_permitted: bool,
+    /// What the peer has SACKed above `snd.una`
+    scoreboard: Scoreboard,
+    /// Both sides agreed to scale their windows, RFC 7323
+    window_scaling: bool,
+    /// Both sides agreed to send timestamps, RFC 7323
+    timestamps: bool,
+    /// TS.Recent, the timestamp we echo back to the peer
+    ts_recent: u32,
+    /// Last.ACK.sent, the acknowledgment number we last sent
+    last_ack_sent: u32,
+    /// Size of the largest packet we send or receive
+    mtu: usize,
+    /// Most payload bytes a segment we send may carry
+    mss: usize,
+    congestion_algorithm: CongestionAlgorithm,
+    congestion: Box<dyn CongestionControl>,
+    /// Delivery rate samples for the congestion control
+    rate: RateSampler,
+    /// When pacing lets the next segment go out
+    next_send: Instant,
+    /// Duplicate ACKs in a row
+    dupacks: u32,
+    /// SND.NXT when we last reacted to a loss, RFC 6582
+    recover: Option<u32>,
+    /// In fast recovery until everything up to `recover` is acked
+    in_recovery: bool,
+    /// What duplicate ACKs add to the congestion window
+    inflation: usize,
+    /// Time based loss detection
+    rack: Rack,
+    /// SND.NXT after the tail loss probe we're waiting on the ACK for
+    tlp_end: Option<u32>,
+    /// Bytes we hold for the application, what's free of it is the receive window
+    rcv_buffer: usize,
+    /// Auto-tuning of `rcv_buffer`
+    rcv_space: RcvSpace,
+    /// Reading opened the receive window, the peer should hear about it
+    window_update: bool,
+    /// Nagle's algorithm is off
+    pub(crate) nodelay: bool,
+    /// How long an ACK may wait, `None` acks every segment right away
+    ack_delay: Option<Duration>,
+    /// Segments received since we last sent an ACK
+    delayed_segments: u32,
+    /// Segments left to ACK right away in quick-ack mode
+    quickacks: u32,
+    /// Probing the peer while the connection is idle, off unless asked for
+    pub(crate) keepalive: Option<Keepalive>,
+    /// `reset` was called, the RST goes out on the next tick
+    reset: bool,
 }
 
 pub struct Timer {
-    send_times: BTreeMap<u32, Instant>,
-    srtt: Duration,
+    /// What's in flight, by the sequence number it starts at
+    send_times: BTreeMap<u32, Transmission>,
+    /// SRTT, `None` until the first measurement
+    srtt: Option<Duration>,
+    /// RTTVAR
+    rttvar: Duration,
+    rto: Duration,
+    /// When the retransmission timer was started, it only runs while there
+    /// is something unacknowledged
+    rto_started: Option<Instant>,
+    /// Timeouts in a row without the peer acknowledging anything new
+    retransmits: u32,
+    /// When the tail loss probe goes out
+    pto: Option<Instant>,
+    /// When the next zero window probe goes out
+    persist: Option<Instant>,
+    /// Zero window probes sent without the window opening
+    persist_backoff: u32,
+    /// When the ACK we're holding back goes out by itself
+    delayed_ack: Option<Instant>,
+    /// When an acceptable segment last arrived
+    last_received: Instant,
+    /// Keepalive probes sent since then
+    keepalive_probes: u32,
+    /// When we last entered TIME-WAIT
+    time_wait: Option<Instant>,
+    /// Start of the clock our timestamps are taken from
+    ts_origin: Instant,
+}
+
+impl Timer {
+    fn new() -> Self {
+        Timer {
+            send_times: Default::default(),
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+            rto_started: None,
+            retransmits: 0,
+            pto: None,
+            persist: None,
+            persist_backoff: 0,
+            delayed_ack: None,
+            last_received: Instant::now(),
+            keepalive_probes: 0,
+            time_wait: None,
+            ts_origin: Instant::now(),
+        }
+    }
+
+    /// RFC 6298 section 2
+    fn on_rtt_sample(&mut self, rtt: Duration) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = self.rttvar * 3 / 4 + delta / 4;
+                srtt * 7 / 8 + rtt / 8
+            }
+        };
+        self.srtt = Some(srtt);
+        self.rto = (srtt + CLOCK_GRANULARITY.max(4 * self.rttvar)).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// A segment occupying `seq..next_seq` went out
+    fn on_send(&mut self, seq: u32, next_seq: u32, retransmission: bool) {
+        let now = Instant::now();
+        let sent = Transmission {
+            end: next_seq,
+            at: now,
+            retransmitted: retransmission,
+            sacked: false,
+            lost: false,
+        };
+        if retransmission {
+            for (&s, t) in self.send_times.iter_mut() {
+                if is_between_wrapping(seq.wrapping_sub(1), s, next_seq) {
+                    t.at = now;
+                    t.retransmitted = true;
+                    t.lost = false;
+                }
+            }
+            self.send_times.entry(seq).or_insert(sent);
+        } else {
+            self.send_times.insert(seq, sent);
+        }
+        if self.rto_started.is_none() {
+            self.rto_started = Some(now);
+        }
+    }
+
+    /// SND.UNA moved forward, `all` if nothing is outstanding anymore
+    fn on_ack(&mut self, all: bool) {
+        self.retransmits = 0;
+        self.rto_started = if all { None } else { Some(Instant::now()) };
+    }
+
+    fn is_expired(&self) -> bool {
+        matches!(self.rto_started, Some(started) if started.elapsed() >= self.rto)
+    }
+
+    /// Schedules the next zero window probe, backing off like the RTO does
+    fn arm_persist(&mut self, now: Instant) {
+        let backoff = 2u32.saturating_pow(self.persist_backoff);
+        self.persist = Some(now + self.rto.saturating_mul(backoff).min(MAX_RTO));
+    }
+
+    /// The timer expired, wait twice as long for the retransmission
+    fn back_off(&mut self) {
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        self.rto_started = Some(Instant::now());
+    }
+
+    fn ts_now(&self) -> u32 {
+        ts_since(self.ts_origin)
+    }
+}
+
+/// TSval, milliseconds since `origin` starting at 1 as a TSecr of 0 means
+/// nothing is echoed
+fn ts_since(origin: Instant) -> u32 {
+    (origin.elapsed().as_millis() as u32).wrapping_add(1)
 }
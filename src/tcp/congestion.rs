@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use self::{bbr::Bbr, cubic::Cubic, reno::NewReno};
+
+mod bbr;
+mod cubic;
+mod reno;
+
+/// What an acknowledgment of new data told the sender
+#[derive(Debug, Clone, Copy)]
+pub struct Ack {
+    /// Bytes the ACK newly acknowledged
+    pub acked: usize,
+    /// Bytes still unacknowledged after it
+    pub in_flight: usize,
+    /// SND.UNA after the ACK
+    pub snd_una: u32,
+    /// SND.NXT when the ACK arrived
+    pub snd_nxt: u32,
+    /// Round trip time measured from the ACK, if it could be timed
+    pub rtt: Option<Duration>,
+    /// Bytes acknowledged over the life of the connection, this ACK included
+    pub delivered: u64,
+    /// How fast the acknowledged data got through
+    pub rate: Option<RateSample>,
+    /// The ACK arrived during fast recovery, when the window must not grow
+    pub in_recovery: bool,
+}
+
+/// A delivery rate sample, taken from the most recently sent segment an ACK covers
+#[derive(Debug, Clone, Copy)]
+pub struct RateSample {
+    /// Bytes per second
+    pub delivery_rate: f64,
+    /// What had been delivered when the segment was sent
+    pub prior_delivered: u64,
+    /// Time the sample was taken over
+    pub interval: Duration,
+    /// We ran out of data to send while it was in flight, so the rate says
+    /// little about the path
+    pub is_app_limited: bool,
+}
+
+/// A congestion controller, consulted by `Connection::on_tick` before it
+/// sends new data
+pub trait CongestionControl: Send {
+    /// New data was acknowledged
+    fn on_ack(&mut self, ack: Ack);
+
+    /// A segment was found lost while the ACK clock kept running, with
+    /// `in_flight` bytes outstanding
+    fn on_loss(&mut self, in_flight: usize);
+
+    /// The retransmission timer expired with `in_flight` bytes outstanding
+    fn on_timeout(&mut self, in_flight: usize);
+
+    /// Congestion window, the most bytes we may have in flight
+    fn cwnd(&self) -> usize;
+
+    /// Slow start threshold
+    fn ssthresh(&self) -> usize;
+
+    /// Bytes per second to spread transmissions out to, `None` sends
+    /// whatever the window allows right away
+    fn pacing_rate(&self) -> Option<f64> {
+        None
+    }
+}
+
+/// The congestion control algorithms a connection can pick
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CongestionAlgorithm {
+    /// RFC 5681 and RFC 6582
+    #[default]
+    NewReno,
+    /// RFC 9438, with HyStart++
+    Cubic,
+    /// BBR v1, draft-cardwell-iccrg-bbr-congestion-control-00, paced
+    Bbr,
+}
+
+impl CongestionAlgorithm {
+    /// A fresh controller for a connection sending `mss` sized segments
+    pub(crate) fn build(self, mss: usize) -> Box<dyn CongestionControl> {
+        match self {
+            CongestionAlgorithm::NewReno => Box::new(NewReno::new(mss)),
+            CongestionAlgorithm::Cubic => Box::new(Cubic::new(mss)),
+            CongestionAlgorithm::Bbr => Box::new(Bbr::new(mss)),
+        }
+    }
+}
+
+/// Initial window, RFC 5681 section 3.1
+fn initial_window(mss: usize) -> usize {
+    (4 * mss).min((2 * mss).max(4380))
+}
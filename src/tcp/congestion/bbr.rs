@@ -0,0 +1,343 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::{initial_window, Ack, CongestionControl};
+
+/// 2/ln(2), the smallest gain that still doubles the sending rate each round
+const HIGH_GAIN: f64 = 2.885;
+/// Pacing gains of the ProbeBW phases, one round trip each
+const PACING_GAIN_CYCLE: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+/// Rounds the bottleneck bandwidth filter remembers
+const BTL_BW_FILTER_LEN: u64 = 10;
+/// How long a min RTT measurement stays valid
+const RT_PROP_FILTER_LEN: Duration = Duration::from_secs(10);
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+/// Smallest window, in segments
+const MIN_PIPE_CWND: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Startup,
+    Drain,
+    ProbeBw,
+    ProbeRtt,
+}
+
+/// BBR v1, draft-cardwell-iccrg-bbr-congestion-control-00.
+///
+/// Builds a model of the path from the bottleneck bandwidth and the round
+/// trip propagation time and sends at that rate, loss alone doesn't slow it down.
+#[derive(Debug)]
+pub struct Bbr {
+    mss: usize,
+    mode: Mode,
+    cwnd: usize,
+    /// Bytes per second
+    pacing_rate: f64,
+    pacing_gain: f64,
+    cwnd_gain: f64,
+    /// Delivery rates of the last rounds and the round they were taken in
+    bw_samples: VecDeque<(u64, f64)>,
+    /// BtlBw, bytes per second
+    btl_bw: f64,
+    /// RTprop and when it was last lowered or refreshed
+    rt_prop: Option<Duration>,
+    rt_prop_stamp: Instant,
+    rt_prop_expired: bool,
+    round_count: u64,
+    /// The round ends once data sent after this much was delivered is acked
+    next_round_delivered: u64,
+    round_start: bool,
+    filled_pipe: bool,
+    full_bw: f64,
+    full_bw_count: u32,
+    cycle_index: usize,
+    cycle_stamp: Instant,
+    /// When ProbeRTT may end, once the window has drained
+    probe_rtt_done: Option<Instant>,
+    probe_rtt_round_done: bool,
+    /// The window before ProbeRTT or a loss, restored afterwards
+    prior_cwnd: usize,
+    /// Packet conservation lasts until this round is over
+    recovery_round: Option<u64>,
+}
+
+impl Bbr {
+    pub fn new(mss: usize) -> Self {
+        let now = Instant::now();
+        let cwnd = initial_window(mss);
+        Bbr {
+            mss,
+            mode: Mode::Startup,
+            cwnd,
+            // nothing measured yet, assume the initial window per millisecond
+            pacing_rate: HIGH_GAIN * cwnd as f64 * 1000.0,
+            pacing_gain: HIGH_GAIN,
+            cwnd_gain: HIGH_GAIN,
+            bw_samples: Default::default(),
+            btl_bw: 0.0,
+            rt_prop: None,
+            rt_prop_stamp: now,
+            rt_prop_expired: false,
+            round_count: 0,
+            next_round_delivered: 0,
+            round_start: false,
+            filled_pipe: false,
+            full_bw: 0.0,
+            full_bw_count: 0,
+            cycle_index: 0,
+            cycle_stamp: now,
+            probe_rtt_done: None,
+            probe_rtt_round_done: false,
+            prior_cwnd: 0,
+            recovery_round: None,
+        }
+    }
+
+    /// `gain` times the estimated bandwidth-delay product
+    fn inflight(&self, gain: f64) -> usize {
+        match self.rt_prop {
+            Some(rt_prop) if self.btl_bw > 0.0 => {
+                (gain * self.btl_bw * rt_prop.as_secs_f64()) as usize
+            }
+            _ => initial_window(self.mss),
+        }
+    }
+
+    fn update_round(&mut self, ack: &Ack) {
+        self.round_start = false;
+        if let Some(rs) = ack.rate {
+            if rs.prior_delivered >= self.next_round_delivered {
+                self.next_round_delivered = ack.delivered;
+                self.round_count += 1;
+                self.round_start = true;
+            }
+        }
+    }
+
+    fn update_btl_bw(&mut self, ack: &Ack) {
+        let Some(rs) = ack.rate else {
+            return;
+        };
+        // app limited samples only count if they show more than we know of
+        if rs.delivery_rate >= self.btl_bw || !rs.is_app_limited {
+            self.bw_samples
+                .push_back((self.round_count, rs.delivery_rate));
+        }
+        while matches!(self.bw_samples.front(), Some(&(round, _)) if round + BTL_BW_FILTER_LEN <= self.round_count)
+        {
+            self.bw_samples.pop_front();
+        }
+        if let Some(max) = self.bw_samples.iter().map(|&(_, bw)| bw).reduce(f64::max) {
+            self.btl_bw = max;
+        }
+    }
+
+    fn update_rt_prop(&mut self, ack: &Ack, now: Instant) {
+        self.rt_prop_expired = now > self.rt_prop_stamp + RT_PROP_FILTER_LEN;
+        if let Some(rtt) = ack.rtt {
+            if self.rt_prop.is_none_or(|rt_prop| rtt <= rt_prop) || self.rt_prop_expired {
+                self.rt_prop = Some(rtt);
+                self.rt_prop_stamp = now;
+            }
+        }
+    }
+
+    fn enter_startup(&mut self) {
+        self.mode = Mode::Startup;
+        self.pacing_gain = HIGH_GAIN;
+        self.cwnd_gain = HIGH_GAIN;
+    }
+
+    /// Startup is over once three rounds in a row didn't grow the bandwidth by a quarter
+    fn check_full_pipe(&mut self, ack: &Ack) {
+        if self.filled_pipe || !self.round_start || ack.rate.is_some_and(|rs| rs.is_app_limited) {
+            return;
+        }
+        if self.btl_bw >= self.full_bw * 1.25 {
+            self.full_bw = self.btl_bw;
+            self.full_bw_count = 0;
+            return;
+        }
+        self.full_bw_count += 1;
+        if self.full_bw_count >= 3 {
+            self.filled_pipe = true;
+        }
+    }
+
+    /// Drain the queue Startup built up
+    fn check_drain(&mut self, ack: &Ack, now: Instant) {
+        if self.mode == Mode::Startup && self.filled_pipe {
+            self.mode = Mode::Drain;
+            self.pacing_gain = 1.0 / HIGH_GAIN;
+            self.cwnd_gain = HIGH_GAIN;
+        }
+        if self.mode == Mode::Drain && ack.in_flight <= self.inflight(1.0) {
+            self.enter_probe_bw(now);
+        }
+    }
+
+    fn enter_probe_bw(&mut self, now: Instant) {
+        self.mode = Mode::ProbeBw;
+        self.pacing_gain = 1.0;
+        self.cwnd_gain = 2.0;
+        // start at a random phase, other than the one draining the queue
+        let random = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos() as usize);
+        self.cycle_index = PACING_GAIN_CYCLE.len() - 1 - random % (PACING_GAIN_CYCLE.len() - 1);
+        self.advance_cycle_phase(now);
+    }
+
+    fn advance_cycle_phase(&mut self, now: Instant) {
+        self.cycle_index = (self.cycle_index + 1) % PACING_GAIN_CYCLE.len();
+        self.cycle_stamp = now;
+        self.pacing_gain = PACING_GAIN_CYCLE[self.cycle_index];
+    }
+
+    fn check_cycle_phase(&mut self, ack: &Ack, now: Instant) {
+        if self.mode != Mode::ProbeBw {
+            return;
+        }
+        let elapsed = self
+            .rt_prop
+            .is_some_and(|rt_prop| now - self.cycle_stamp > rt_prop);
+        let next = if self.pacing_gain > 1.0 {
+            // probe until the extra data is actually in flight
+            elapsed && ack.in_flight >= self.inflight(self.pacing_gain)
+        } else if self.pacing_gain < 1.0 {
+            elapsed || ack.in_flight <= self.inflight(1.0)
+        } else {
+            elapsed
+        };
+        if next {
+            self.advance_cycle_phase(now);
+        }
+    }
+
+    /// Every `RT_PROP_FILTER_LEN` the queue is drained for a moment to see
+    /// the bare round trip time again
+    fn check_probe_rtt(&mut self, ack: &Ack, now: Instant) {
+        if self.mode != Mode::ProbeRtt && self.rt_prop_expired {
+            self.mode = Mode::ProbeRtt;
+            self.pacing_gain = 1.0;
+            self.cwnd_gain = 1.0;
+            self.save_cwnd();
+            self.probe_rtt_done = None;
+        }
+        if self.mode != Mode::ProbeRtt {
+            return;
+        }
+
+        match self.probe_rtt_done {
+            None if ack.in_flight <= MIN_PIPE_CWND * self.mss => {
+                self.probe_rtt_done = Some(now + PROBE_RTT_DURATION);
+                self.probe_rtt_round_done = false;
+                self.next_round_delivered = ack.delivered;
+            }
+            None => {}
+            Some(done) => {
+                self.probe_rtt_round_done |= self.round_start;
+                if self.probe_rtt_round_done && now > done {
+                    self.rt_prop_stamp = now;
+                    self.cwnd = self.cwnd.max(self.prior_cwnd);
+                    if self.filled_pipe {
+                        self.enter_probe_bw(now);
+                    } else {
+                        self.enter_startup();
+                    }
+                }
+            }
+        }
+    }
+
+    fn save_cwnd(&mut self) {
+        self.prior_cwnd = if self.recovery_round.is_none() && self.mode != Mode::ProbeRtt {
+            self.cwnd
+        } else {
+            self.prior_cwnd.max(self.cwnd)
+        };
+    }
+
+    fn set_pacing_rate(&mut self) {
+        if self.btl_bw == 0.0 {
+            return;
+        }
+        let rate = self.pacing_gain * self.btl_bw;
+        // Startup never slows down, the estimate may still be catching up
+        if self.filled_pipe || rate > self.pacing_rate {
+            self.pacing_rate = rate;
+        }
+    }
+
+    fn set_cwnd(&mut self, ack: &Ack) {
+        // a few segments on top for delayed and stretched ACKs
+        let target = self.inflight(self.cwnd_gain) + 3 * self.mss;
+        if let Some(round) = self.recovery_round {
+            if self.round_count > round {
+                self.recovery_round = None;
+                self.cwnd = self.cwnd.max(self.prior_cwnd);
+            } else {
+                // packet conservation, send one segment for each one delivered
+                self.cwnd = self.cwnd.max(ack.in_flight + ack.acked);
+            }
+        }
+
+        if self.filled_pipe {
+            self.cwnd = (self.cwnd + ack.acked).min(target);
+        } else if self.cwnd < target || ack.delivered < initial_window(self.mss) as u64 {
+            self.cwnd += ack.acked;
+        }
+        self.cwnd = self.cwnd.max(MIN_PIPE_CWND * self.mss);
+        if self.mode == Mode::ProbeRtt {
+            self.cwnd = self.cwnd.min(MIN_PIPE_CWND * self.mss);
+        }
+    }
+}
+
+impl CongestionControl for Bbr {
+    fn on_ack(&mut self, ack: Ack) {
+        let now = Instant::now();
+        self.update_round(&ack);
+        self.update_btl_bw(&ack);
+        self.check_cycle_phase(&ack, now);
+        self.check_full_pipe(&ack);
+        self.check_drain(&ack, now);
+        self.update_rt_prop(&ack, now);
+        self.check_probe_rtt(&ack, now);
+
+        self.set_pacing_rate();
+        self.set_cwnd(&ack);
+    }
+
+    fn on_loss(&mut self, in_flight: usize) {
+        self.save_cwnd();
+        self.cwnd = in_flight + self.mss;
+        self.recovery_round = Some(self.round_count + 1);
+    }
+
+    fn on_timeout(&mut self, _in_flight: usize) {
+        self.save_cwnd();
+        self.cwnd = self.mss;
+        self.recovery_round = Some(self.round_count + 1);
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> usize {
+        // BBR doesn't slow start, only Startup's window growth comes close
+        if self.filled_pipe {
+            self.cwnd
+        } else {
+            usize::MAX
+        }
+    }
+
+    fn pacing_rate(&self) -> Option<f64> {
+        Some(self.pacing_rate)
+    }
+}
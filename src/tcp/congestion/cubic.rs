@@ -0,0 +1,244 @@
+use std::time::{Duration, Instant};
+
+use super::{initial_window, Ack, CongestionControl};
+use crate::tcp::wrapping_lt;
+
+/// C, scales how fast the window grows, RFC 9438 section 5
+const C: f64 = 0.4;
+/// β_cubic, what's left of the window after a congestion event
+const BETA: f64 = 0.7;
+
+/// RFC 9438 CUBIC, with HyStart++ (RFC 9406) leaving the initial slow start
+/// before it overshoots
+#[derive(Debug)]
+pub struct Cubic {
+    mss: f64,
+    cwnd: f64,
+    ssthresh: f64,
+    /// W_max, the window right before the last reduction
+    w_max: f64,
+    /// K, how long it takes to grow back to `w_max`
+    k: Duration,
+    /// When the current congestion avoidance stage started
+    epoch: Option<Instant>,
+    /// W_est, what Reno would have grown the window to in this epoch
+    w_est: f64,
+    srtt: Option<Duration>,
+    /// Only the initial slow start is left early, RFC 9406 section 4.3
+    hystart: Option<HyStart>,
+}
+
+impl Cubic {
+    pub fn new(mss: usize) -> Self {
+        Cubic {
+            mss: mss as f64,
+            cwnd: initial_window(mss) as f64,
+            ssthresh: f64::INFINITY,
+            w_max: 0.0,
+            k: Duration::ZERO,
+            epoch: None,
+            w_est: 0.0,
+            srtt: None,
+            hystart: Some(HyStart::default()),
+        }
+    }
+
+    /// W_cubic(t), in bytes
+    fn w_cubic(&self, t: Duration) -> f64 {
+        let t = t.as_secs_f64() - self.k.as_secs_f64();
+        C * t.powi(3) * self.mss + self.w_max
+    }
+
+    /// Multiplicative decrease, RFC 9438 section 4.6 and 4.7
+    fn reduce(&mut self) {
+        // fast convergence, let go of bandwidth for newer flows
+        self.w_max = if self.cwnd < self.w_max {
+            self.cwnd * (1.0 + BETA) / 2.0
+        } else {
+            self.cwnd
+        };
+        self.ssthresh = (self.cwnd * BETA).max(2.0 * self.mss);
+        self.epoch = None;
+        self.hystart = None;
+    }
+
+    fn congestion_avoidance(&mut self, ack: Ack) {
+        let now = Instant::now();
+        let epoch = match self.epoch {
+            Some(epoch) => epoch,
+            None => {
+                if self.cwnd < self.w_max {
+                    let k = ((self.w_max - self.cwnd) / self.mss / C).cbrt();
+                    self.k = Duration::from_secs_f64(k);
+                } else {
+                    // left slow start without a congestion event or after a timeout
+                    self.k = Duration::ZERO;
+                    self.w_max = self.cwnd;
+                }
+                self.w_est = self.cwnd;
+                *self.epoch.insert(now)
+            }
+        };
+
+        let t = now - epoch;
+        let rtt = self.srtt.unwrap_or_default();
+        let target = self.w_cubic(t + rtt).clamp(self.cwnd, 1.5 * self.cwnd);
+
+        // the Reno-friendly region, RFC 9438 section 4.3
+        let alpha = if self.w_est < self.w_max {
+            3.0 * (1.0 - BETA) / (1.0 + BETA)
+        } else {
+            1.0
+        };
+        self.w_est += alpha * ack.acked as f64 * self.mss / self.cwnd;
+
+        if self.w_cubic(t) < self.w_est {
+            self.cwnd = self.w_est;
+        } else {
+            self.cwnd += (target - self.cwnd) * ack.acked as f64 / self.cwnd;
+        }
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn on_ack(&mut self, ack: Ack) {
+        if let Some(rtt) = ack.rtt {
+            self.srtt = Some(match self.srtt {
+                Some(srtt) => srtt * 7 / 8 + rtt / 8,
+                None => rtt,
+            });
+        }
+        if ack.in_recovery {
+            return;
+        }
+
+        if self.cwnd >= self.ssthresh {
+            self.congestion_avoidance(ack);
+            return;
+        }
+
+        let mss = self.mss as usize;
+        let increase = match &mut self.hystart {
+            Some(hystart) => match hystart.on_ack(ack, mss) {
+                Some(increase) => increase,
+                None => {
+                    // HyStart++ is done, go straight to congestion avoidance
+                    self.ssthresh = self.cwnd;
+                    self.hystart = None;
+                    return;
+                }
+            },
+            None => ack.acked.min(mss),
+        };
+        self.cwnd += increase as f64;
+    }
+
+    fn on_loss(&mut self, _in_flight: usize) {
+        self.reduce();
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_timeout(&mut self, _in_flight: usize) {
+        self.reduce();
+        // the first epoch after a timeout starts over from the window we
+        // grow back to, not from W_max
+        self.w_max = 0.0;
+        self.cwnd = self.mss;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd as usize
+    }
+
+    fn ssthresh(&self) -> usize {
+        if self.ssthresh.is_finite() {
+            self.ssthresh as usize
+        } else {
+            usize::MAX
+        }
+    }
+}
+
+/// Segments slow start may grow the window by per ACK, L for unpaced senders
+const HYSTART_L: usize = 8;
+const MIN_RTT_THRESH: Duration = Duration::from_millis(4);
+const MAX_RTT_THRESH: Duration = Duration::from_millis(16);
+const MIN_RTT_DIVISOR: u32 = 8;
+/// RTT samples a round needs before its minimum is trusted
+const N_RTT_SAMPLE: u32 = 8;
+const CSS_GROWTH_DIVISOR: usize = 4;
+/// Rounds of Conservative Slow Start before moving on to congestion avoidance
+const CSS_ROUNDS: u32 = 5;
+
+/// RFC 9406, watches the RTT of each round of slow start and slows down once
+/// it starts to rise
+#[derive(Debug, Default)]
+struct HyStart {
+    /// SND.NXT when the current round started, it ends once that is acked
+    window_end: Option<u32>,
+    last_round_min_rtt: Option<Duration>,
+    current_round_min_rtt: Option<Duration>,
+    rtt_sample_count: u32,
+    /// cssBaselineMinRtt, set while in Conservative Slow Start
+    css_baseline_min_rtt: Option<Duration>,
+    css_rounds: u32,
+}
+
+impl HyStart {
+    /// How much slow start grows the window for `ack`, `None` once it should
+    /// make way for congestion avoidance
+    fn on_ack(&mut self, ack: Ack, mss: usize) -> Option<usize> {
+        // a round ends once everything sent when it began is acked
+        if self
+            .window_end
+            .is_none_or(|end| !wrapping_lt(ack.snd_una, end))
+        {
+            self.last_round_min_rtt = self.current_round_min_rtt.take();
+            self.rtt_sample_count = 0;
+            self.window_end = Some(ack.snd_nxt);
+            if self.css_baseline_min_rtt.is_some() {
+                self.css_rounds += 1;
+                if self.css_rounds >= CSS_ROUNDS {
+                    return None;
+                }
+            }
+        }
+        if let Some(rtt) = ack.rtt {
+            self.current_round_min_rtt =
+                Some(self.current_round_min_rtt.map_or(rtt, |m| m.min(rtt)));
+            self.rtt_sample_count += 1;
+        }
+
+        let increase = ack.acked.min(HYSTART_L * mss);
+        let (Some(current), true) = (
+            self.current_round_min_rtt,
+            self.rtt_sample_count >= N_RTT_SAMPLE,
+        ) else {
+            return Some(self.growth(increase));
+        };
+
+        match (self.css_baseline_min_rtt, self.last_round_min_rtt) {
+            (Some(baseline), _) if current < baseline => {
+                // the RTT went back down, the rise was a false alarm
+                self.css_baseline_min_rtt = None;
+                self.css_rounds = 0;
+            }
+            (None, Some(last)) => {
+                let thresh = (last / MIN_RTT_DIVISOR).clamp(MIN_RTT_THRESH, MAX_RTT_THRESH);
+                if current >= last + thresh {
+                    self.css_baseline_min_rtt = Some(current);
+                }
+            }
+            _ => {}
+        }
+        Some(self.growth(increase))
+    }
+
+    fn growth(&self, increase: usize) -> usize {
+        if self.css_baseline_min_rtt.is_some() {
+            increase / CSS_GROWTH_DIVISOR
+        } else {
+            increase
+        }
+    }
+}
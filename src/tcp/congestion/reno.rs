@@ -0,0 +1,68 @@
+use super::{initial_window, Ack, CongestionControl};
+
+/// Slow start and congestion avoidance of RFC 5681, with the window halving
+/// once per loss event as NewReno (RFC 6582) does
+#[derive(Debug)]
+pub struct NewReno {
+    mss: usize,
+    cwnd: usize,
+    ssthresh: usize,
+    /// Bytes acknowledged since `cwnd` last grew in congestion avoidance
+    acked: usize,
+}
+
+impl NewReno {
+    pub fn new(mss: usize) -> Self {
+        NewReno {
+            mss,
+            cwnd: initial_window(mss),
+            // arbitrarily high, RFC 5681 section 3.1
+            ssthresh: usize::MAX,
+            acked: 0,
+        }
+    }
+
+    /// Half of what's in flight, but at least two segments, equation (4)
+    fn halve(&mut self, in_flight: usize) {
+        self.ssthresh = (in_flight / 2).max(2 * self.mss);
+        self.acked = 0;
+    }
+}
+
+impl CongestionControl for NewReno {
+    fn on_ack(&mut self, ack: Ack) {
+        if ack.in_recovery {
+            return;
+        }
+        if self.cwnd < self.ssthresh {
+            // slow start, one segment more per segment acked
+            self.cwnd += ack.acked.min(self.mss);
+        } else {
+            // congestion avoidance, one segment more per window acked
+            self.acked += ack.acked;
+            if self.acked >= self.cwnd {
+                self.acked -= self.cwnd;
+                self.cwnd += self.mss;
+            }
+        }
+    }
+
+    fn on_loss(&mut self, in_flight: usize) {
+        self.halve(in_flight);
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_timeout(&mut self, in_flight: usize) {
+        self.halve(in_flight);
+        // the loss window, one segment
+        self.cwnd = self.mss;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+}
@@ -0,0 +1,49 @@
+use std::{collections::hash_map::RandomState, hash::BuildHasher, time::Instant};
+
+use crate::Quad;
+
+/// Picks the initial send sequence number of new connections
+pub trait IsnGenerator: Send {
+    fn isn(&mut self, quad: &Quad) -> u32;
+}
+
+/// Any closure will do, tests can pin the ISS with `|_: &Quad| 0`
+impl<F: FnMut(&Quad) -> u32 + Send> IsnGenerator for F {
+    fn isn(&mut self, quad: &Quad) -> u32 {
+        self(quad)
+    }
+}
+
+/// ISN = M + F(localip, localport, remoteip, remoteport, secretkey), RFC 6528.
+///
+/// F is SipHash keyed with random keys, so nobody off path can guess the
+/// ISS of a connection, and M moves on between incarnations of one quad so
+/// their sequence numbers don't overlap.
+pub struct KeyedIsn {
+    secret: RandomState,
+    origin: Instant,
+}
+
+impl KeyedIsn {
+    /// A generator with a fresh secret
+    pub fn new() -> Self {
+        KeyedIsn {
+            secret: RandomState::new(),
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for KeyedIsn {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IsnGenerator for KeyedIsn {
+    fn isn(&mut self, quad: &Quad) -> u32 {
+        // M, a timer ticking every 4 microseconds
+        let m = (self.origin.elapsed().as_micros() / 4) as u32;
+        m.wrapping_add(self.secret.hash_one(quad) as u32)
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use super::wrapping_lt;
+
+/// A segment we sent that hasn't been cumulatively acknowledged yet
+#[derive(Debug, Clone, Copy)]
+pub struct Transmission {
+    /// Sequence number right after the segment
+    pub end: u32,
+    /// When it last went out
+    pub at: Instant,
+    pub retransmitted: bool,
+    /// The peer SACKed it
+    pub sacked: bool,
+    /// Given up on by RACK and not sent again since
+    pub lost: bool,
+}
+
+/// RFC 8985 RACK, a segment is lost once another one sent sufficiently
+/// later than it was delivered
+#[derive(Debug, Default)]
+pub struct Rack {
+    /// RACK.xmit_ts, when the most recently sent of the delivered segments went out
+    xmit_ts: Option<Instant>,
+    /// RACK.end_seq, where that segment ends
+    end_seq: u32,
+    /// RACK.rtt, the round trip time of that segment
+    rtt: Duration,
+    min_rtt: Option<Duration>,
+    /// RACK.fack, the highest sequence number delivered
+    fack: Option<u32>,
+    /// Segments arrived out of order, so the reordering window stays open
+    reordering_seen: bool,
+    /// When the segments still inside the reordering window time out
+    pub timeout: Option<Instant>,
+}
+
+impl Rack {
+    /// `t` was cumulatively acknowledged or SACKed, RFC 8985 section 6.2
+    pub fn on_delivered(&mut self, t: &Transmission, now: Instant) {
+        let rtt = now - t.at;
+        if t.retransmitted && self.min_rtt.is_some_and(|min_rtt| rtt < min_rtt) {
+            // too quick for the retransmission, the original was delivered
+            return;
+        }
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt)));
+
+        match self.fack {
+            Some(fack) if wrapping_lt(t.end, fack) => {
+                self.reordering_seen |= !t.retransmitted;
+            }
+            _ => self.fack = Some(t.end),
+        }
+
+        let newer = match self.xmit_ts {
+            Some(xmit_ts) => {
+                xmit_ts < t.at || (xmit_ts == t.at && wrapping_lt(self.end_seq, t.end))
+            }
+            None => true,
+        };
+        if newer {
+            self.xmit_ts = Some(t.at);
+            self.end_seq = t.end;
+            self.rtt = rtt;
+        }
+    }
+
+    /// RACK.reo_wnd, how much later than expected a segment may still arrive
+    fn reo_wnd(&self, in_recovery: bool, srtt: Duration) -> Duration {
+        if !self.reordering_seen && in_recovery {
+            return Duration::ZERO;
+        }
+        (self.min_rtt.unwrap_or_default() / 4).min(srtt)
+    }
+
+    /// Marks the segments sent before the most recently delivered one as
+    /// lost once the reordering window has passed for them, and arms
+    /// `timeout` for the others. Returns whether anything new was lost.
+    pub fn detect_loss(
+        &mut self,
+        send_times: &mut BTreeMap<u32, Transmission>,
+        in_recovery: bool,
+        srtt: Duration,
+        now: Instant,
+    ) -> bool {
+        self.timeout = None;
+        let Some(xmit_ts) = self.xmit_ts else {
+            return false;
+        };
+
+        let reo_wnd = self.reo_wnd(in_recovery, srtt);
+        let mut lost = false;
+        for t in send_times.values_mut() {
+            if t.sacked || t.lost {
+                continue;
+            }
+            let sent_before =
+                t.at < xmit_ts || (t.at == xmit_ts && wrapping_lt(t.end, self.end_seq));
+            if !sent_before {
+                continue;
+            }
+
+            let deadline = t.at + self.rtt + reo_wnd;
+            if deadline <= now {
+                t.lost = true;
+                lost = true;
+            } else if self.timeout.is_none_or(|timeout| deadline < timeout) {
+                self.timeout = Some(deadline);
+            }
+        }
+        lost
+    }
+}
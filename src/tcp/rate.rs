@@ -0,0 +1,111 @@
+use std::{collections::BTreeMap, time::Instant};
+
+use super::{congestion::RateSample, is_between_wrapping};
+
+/// Delivery rate estimation, draft-cheng-iccrg-delivery-rate-estimation.
+///
+/// Every segment remembers how much had been delivered when it went out, so
+/// the ACK for it tells how fast data got through since.
+#[derive(Debug)]
+pub struct RateSampler {
+    /// C.delivered, bytes acknowledged over the life of the connection
+    delivered: u64,
+    /// C.delivered_time, when `delivered` last grew
+    delivered_time: Instant,
+    /// C.first_sent_time, send time of the segment the current sample starts at
+    first_sent_time: Instant,
+    /// C.app_limited, samples are app limited until `delivered` passes this
+    app_limited: u64,
+    sent: BTreeMap<u32, Sent>,
+}
+
+/// What we knew when a segment went out
+#[derive(Debug, Clone, Copy)]
+struct Sent {
+    at: Instant,
+    delivered: u64,
+    delivered_time: Instant,
+    first_sent_time: Instant,
+    is_app_limited: bool,
+}
+
+impl RateSampler {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        RateSampler {
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            app_limited: 0,
+            sent: Default::default(),
+        }
+    }
+
+    pub fn delivered(&self) -> u64 {
+        self.delivered
+    }
+
+    /// A segment starting at `seq` went out, `idle` if nothing was in flight
+    pub fn on_send(&mut self, seq: u32, idle: bool) {
+        let now = Instant::now();
+        if idle {
+            self.first_sent_time = now;
+            self.delivered_time = now;
+        }
+        self.sent.insert(
+            seq,
+            Sent {
+                at: now,
+                delivered: self.delivered,
+                delivered_time: self.delivered_time,
+                first_sent_time: self.first_sent_time,
+                is_app_limited: self.app_limited != 0,
+            },
+        );
+    }
+
+    /// We had nothing to send while the windows would have let us, with
+    /// `in_flight` bytes outstanding
+    pub fn on_app_limited(&mut self, in_flight: usize) {
+        self.app_limited = (self.delivered + in_flight as u64).max(1);
+    }
+
+    /// SND.UNA moved from `una` to `ackn`
+    pub fn on_ack(&mut self, una: u32, ackn: u32) -> Option<RateSample> {
+        let now = Instant::now();
+        self.delivered += ackn.wrapping_sub(una) as u64;
+        if self.app_limited != 0 && self.delivered > self.app_limited {
+            self.app_limited = 0;
+        }
+
+        // the most recently sent of the acked segments has the freshest state
+        let mut newest: Option<Sent> = None;
+        self.sent.retain(|&seq, sent| {
+            if !is_between_wrapping(una.wrapping_sub(1), seq, ackn) {
+                return true;
+            }
+            if newest.is_none_or(|n| n.at < sent.at) {
+                newest = Some(*sent);
+            }
+            false
+        });
+        let p = newest?;
+
+        let send_elapsed = p.at - p.first_sent_time;
+        let ack_elapsed = now - p.delivered_time;
+        self.first_sent_time = p.at;
+        self.delivered_time = now;
+
+        // the slower of the two can't be beaten by the path
+        let interval = send_elapsed.max(ack_elapsed);
+        if interval.is_zero() {
+            return None;
+        }
+        Some(RateSample {
+            delivery_rate: (self.delivered - p.delivered) as f64 / interval.as_secs_f64(),
+            prior_delivered: p.delivered,
+            interval,
+            is_app_limited: p.is_app_limited,
+        })
+    }
+}
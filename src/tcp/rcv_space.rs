@@ -0,0 +1,64 @@
+use std::time::{Duration, Instant};
+
+use super::TICK;
+
+/// Dynamic right sizing of the receive buffer, like Linux's
+/// `tcp_rcv_space_adjust`.
+///
+/// Once per RTT we look at how much the application read in it. To keep the
+/// sender from stalling on the window, the buffer has to hold twice that: one
+/// RTT worth in flight and one the application hasn't got to yet.
+#[derive(Debug)]
+pub struct RcvSpace {
+    /// Most the application read in one RTT so far
+    space: usize,
+    /// Read since `time`
+    copied: usize,
+    /// Start of the current measurement
+    time: Instant,
+    /// RTT as seen by the receiver, from the timestamps the peer echoes
+    rtt: Option<Duration>,
+}
+
+impl RcvSpace {
+    pub fn new() -> Self {
+        RcvSpace {
+            space: 0,
+            copied: 0,
+            time: Instant::now(),
+            rtt: None,
+        }
+    }
+
+    pub fn on_rtt_sample(&mut self, rtt: Duration) {
+        // smoothed like SRTT, the peer holding on to our ACKs inflates single samples
+        self.rtt = Some(match self.rtt {
+            Some(prev) => (prev * 7 + rtt) / 8,
+            None => rtt,
+        });
+    }
+
+    /// The application read `n` bytes, returns the buffer size it would take
+    /// to keep up with it once an RTT has passed and the reads grew
+    pub fn on_read(&mut self, n: usize, srtt: Option<Duration>, now: Instant) -> Option<usize> {
+        self.copied += n;
+        // our timestamps are in milliseconds, local peers measure as zero
+        let rtt = self.rtt.or(srtt)?.max(TICK);
+        if now.duration_since(self.time) < rtt {
+            return None;
+        }
+
+        let copied = std::mem::take(&mut self.copied);
+        self.time = now;
+        if copied <= self.space {
+            return None;
+        }
+
+        let mut wanted = 2 * copied;
+        // the sender is likely still in slow start, leave room for it to keep growing
+        let growth = (copied - self.space).min(self.space);
+        wanted += (wanted * growth).checked_div(self.space).unwrap_or(0);
+        self.space = copied;
+        Some(wanted)
+    }
+}
@@ -0,0 +1,155 @@
+/// Segments that arrived ahead of RCV.NXT, kept until the gap in front of
+/// them is filled.
+///
+/// Ranges are disjoint and never touch each other, ordered by sequence
+/// number. Everything stored here lies within the receive window, so
+/// comparing distances from RCV.NXT orders them correctly across wrap around.
+#[derive(Debug, Default)]
+pub struct Reassembly {
+    segments: Vec<(u32, Vec<u8>)>,
+    /// Sequence number of a FIN that arrived ahead of RCV.NXT
+    fin: Option<u32>,
+    /// Where the most recently stored segment starts
+    last: Option<u32>,
+}
+
+impl Reassembly {
+    /// Stores `data` starting at `seq`, which must lie after `nxt`, merging it
+    /// with whatever overlaps or adjoins it
+    pub fn insert(&mut self, nxt: u32, seq: u32, data: &[u8], fin: bool) {
+        if fin {
+            self.fin = Some(seq.wrapping_add(data.len() as u32));
+        }
+        if data.is_empty() {
+            return;
+        }
+        self.last = Some(seq);
+
+        let mut start = seq.wrapping_sub(nxt) as usize;
+        let mut merged = data.to_vec();
+        let mut kept = Vec::with_capacity(self.segments.len() + 1);
+        for (s, d) in self.segments.drain(..) {
+            let s_start = s.wrapping_sub(nxt) as usize;
+            let s_end = s_start + d.len();
+            let end = start + merged.len();
+            if s_end < start || end < s_start {
+                kept.push((s, d));
+                continue;
+            }
+
+            let (new_start, new_end) = (start.min(s_start), end.max(s_end));
+            let mut buf = vec![0; new_end - new_start];
+            buf[s_start - new_start..s_end - new_start].copy_from_slice(&d);
+            buf[start - new_start..end - new_start].copy_from_slice(&merged);
+            start = new_start;
+            merged = buf;
+        }
+        kept.push((nxt.wrapping_add(start as u32), merged));
+        kept.sort_by_key(|(s, _)| s.wrapping_sub(nxt));
+        self.segments = kept;
+    }
+
+    /// Takes out the bytes that are now contiguous with `nxt`, and whether the
+    /// FIN directly follows them
+    pub fn pop(&mut self, mut nxt: u32) -> (Vec<u8>, bool) {
+        let mut ready = Vec::new();
+        while let Some((s, d)) = self.segments.first() {
+            // how far the segment reaches back behind nxt
+            let behind = nxt.wrapping_sub(*s) as i32;
+            if behind < 0 {
+                break;
+            }
+
+            let behind = behind as usize;
+            if behind < d.len() {
+                ready.extend_from_slice(&d[behind..]);
+                nxt = nxt.wrapping_add((d.len() - behind) as u32);
+            }
+            self.segments.remove(0);
+        }
+
+        let fin = self.fin == Some(nxt);
+        if fin {
+            self.fin = None;
+        }
+        (ready, fin)
+    }
+
+    /// Bytes held
+    pub fn buffered(&self) -> usize {
+        self.segments.iter().map(|(_, d)| d.len()).sum()
+    }
+
+    /// SACK blocks for what we hold, the one holding the most recently
+    /// received segment first as RFC 2018 asks
+    pub fn blocks(&self) -> Vec<(u32, u32)> {
+        let mut blocks: Vec<_> = self
+            .segments
+            .iter()
+            .map(|(s, d)| (*s, s.wrapping_add(d.len() as u32)))
+            .collect();
+        if let Some(last) = self.last {
+            if let Some(i) = blocks
+                .iter()
+                .position(|&(l, r)| last.wrapping_sub(l) < r.wrapping_sub(l))
+            {
+                blocks[..=i].rotate_right(1);
+            }
+        }
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_overlapping_segments() {
+        let mut r = Reassembly::default();
+        r.insert(100, 110, b"abcdef", false);
+        r.insert(100, 113, b"defghi", false);
+        assert_eq!(r.blocks(), vec![(110, 119)]);
+        assert_eq!(r.buffered(), 9);
+
+        r.insert(100, 105, b"vwxyzabcdefghijk", false);
+        assert_eq!(r.blocks(), vec![(105, 121)]);
+        assert_eq!(r.pop(105), (b"vwxyzabcdefghijk".to_vec(), false));
+    }
+
+    #[test]
+    fn merges_adjacent_segments() {
+        let mut r = Reassembly::default();
+        r.insert(100, 110, b"abc", false);
+        r.insert(100, 116, b"ghi", false);
+        assert_eq!(r.blocks(), vec![(116, 119), (110, 113)]);
+
+        r.insert(100, 113, b"def", false);
+        assert_eq!(r.blocks(), vec![(110, 119)]);
+        assert_eq!(r.pop(110), (b"abcdefghi".to_vec(), false));
+    }
+
+    #[test]
+    fn pops_only_what_is_contiguous() {
+        let mut r = Reassembly::default();
+        r.insert(100, 104, b"efg", false);
+        r.insert(100, 110, b"klm", true);
+        assert_eq!(r.pop(100), (vec![], false));
+        // the first segment reaches back behind nxt by a byte
+        assert_eq!(r.pop(105), (b"fg".to_vec(), false));
+        assert_eq!(r.buffered(), 3);
+        assert_eq!(r.pop(110), (b"klm".to_vec(), true));
+        assert_eq!(r.buffered(), 0);
+    }
+
+    #[test]
+    fn pops_across_wrap_around() {
+        let nxt = u32::MAX - 2;
+        let mut r = Reassembly::default();
+        r.insert(nxt, 2, b"fgh", true);
+        r.insert(nxt, nxt.wrapping_add(1), b"bcde", false);
+        assert_eq!(r.blocks(), vec![(nxt.wrapping_add(1), 5)]);
+        assert_eq!(r.pop(nxt), (vec![], false));
+        assert_eq!(r.pop(nxt.wrapping_add(1)), (b"bcdefgh".to_vec(), true));
+    }
+}
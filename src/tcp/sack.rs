@@ -0,0 +1,120 @@
+/// The sender's view of what the peer has selectively acknowledged, RFC 2018.
+///
+/// Ranges are `[left, right)`, disjoint and ordered, and all lie between
+/// SND.UNA and SND.NXT. The peer may renege on them, so SACKed data stays
+/// in `unacked` until it is cumulatively acknowledged.
+#[derive(Debug, Default)]
+pub struct Scoreboard {
+    sacked: Vec<(u32, u32)>,
+}
+
+impl Scoreboard {
+    /// Records a SACK block, ignoring blocks outside of SND.UNA..=SND.NXT
+    pub fn insert(&mut self, una: u32, nxt: u32, left: u32, right: u32) {
+        let (mut start, mut end) = (left.wrapping_sub(una), right.wrapping_sub(una));
+        if start >= end || end > nxt.wrapping_sub(una) {
+            return;
+        }
+
+        let mut kept = Vec::with_capacity(self.sacked.len() + 1);
+        for (l, r) in self.sacked.drain(..) {
+            let (s, e) = (l.wrapping_sub(una), r.wrapping_sub(una));
+            if e < start || end < s {
+                kept.push((l, r));
+            } else {
+                start = start.min(s);
+                end = end.max(e);
+            }
+        }
+        kept.push((una.wrapping_add(start), una.wrapping_add(end)));
+        kept.sort_by_key(|(l, _)| l.wrapping_sub(una));
+        self.sacked = kept;
+    }
+
+    /// Whether `left..right` was SACKed as a whole
+    pub fn covers(&self, una: u32, left: u32, right: u32) -> bool {
+        let (start, end) = (left.wrapping_sub(una), right.wrapping_sub(una));
+        self.sacked
+            .iter()
+            .any(|&(l, r)| l.wrapping_sub(una) <= start && end <= r.wrapping_sub(una))
+    }
+
+    /// Forgets everything the cumulative ACK has moved past
+    pub fn advance(&mut self, old_una: u32, una: u32) {
+        let acked = una.wrapping_sub(old_una);
+        self.sacked.retain_mut(|(l, r)| {
+            if r.wrapping_sub(old_una) <= acked {
+                return false;
+            }
+            if l.wrapping_sub(old_una) < acked {
+                *l = una;
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_blocks() {
+        let mut s = Scoreboard::default();
+        s.insert(1000, 2000, 1100, 1200);
+        s.insert(1000, 2000, 1200, 1300);
+        assert!(s.covers(1000, 1100, 1300));
+
+        s.insert(1000, 2000, 1250, 1400);
+        s.insert(1000, 2000, 1500, 1600);
+        assert!(s.covers(1000, 1100, 1400));
+        assert!(s.covers(1000, 1500, 1600));
+        assert!(!s.covers(1000, 1100, 1600));
+
+        // filling the gap joins both sides
+        s.insert(1000, 2000, 1400, 1500);
+        assert!(s.covers(1000, 1100, 1600));
+        assert!(!s.covers(1000, 1000, 1100));
+    }
+
+    #[test]
+    fn ignores_blocks_outside_the_window() {
+        let mut s = Scoreboard::default();
+        s.insert(1000, 2000, 900, 1100);
+        s.insert(1000, 2000, 1900, 2100);
+        s.insert(1000, 2000, 1500, 1500);
+        assert!(!s.covers(1000, 1000, 1100));
+        assert!(!s.covers(1000, 1900, 2000));
+        assert!(!s.covers(1000, 1499, 1501));
+    }
+
+    #[test]
+    fn advance_trims_and_forgets() {
+        let mut s = Scoreboard::default();
+        s.insert(1000, 2000, 1100, 1300);
+        s.insert(1000, 2000, 1500, 1600);
+
+        s.advance(1000, 1200);
+        assert!(s.covers(1200, 1200, 1300));
+        assert!(s.covers(1200, 1500, 1600));
+
+        s.advance(1200, 1550);
+        assert!(s.covers(1550, 1550, 1600));
+        assert!(!s.covers(1550, 1200, 1300));
+    }
+
+    #[test]
+    fn works_across_wrap_around() {
+        let una = u32::MAX - 100;
+        let nxt = 1000;
+        let mut s = Scoreboard::default();
+        s.insert(una, nxt, u32::MAX - 50, 50);
+        s.insert(una, nxt, 50, 100);
+        assert!(s.covers(una, u32::MAX - 50, 100));
+        assert!(s.covers(una, u32::MAX, 10));
+
+        s.advance(una, 20);
+        assert!(s.covers(20, 20, 100));
+        assert!(!s.covers(20, 20, 101));
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    time::{Duration, Instant},
+};
+
+use super::{SynOptions, MAX_WINDOW_SHIFT, MIN_MSS};
+use crate::Quad;
+
+/// MSS values a cookie can carry, the peer's is rounded down to one of them
+const MSS_TABLE: [u16; 8] = [MIN_MSS as u16, 536, 1200, 1360, 1400, 1440, 1452, 1460];
+/// A cookie is good for the period it was made in and the one after
+const PERIOD: Duration = Duration::from_secs(64);
+/// Bits of the cookie that authenticate it
+const MAC_BITS: u32 = 19;
+
+/// SYN cookies, RFC 4987 section 3.6.
+///
+/// When the half-open queue is full we answer SYNs without keeping any
+/// state, the ISS of the SYN-ACK encodes what we need to know about the SYN:
+///
+/// ```text
+///  31      27 26   24 23      20   19   18                 0
+/// +----------+-------+----------+------+--------------------+
+/// |  period  |  MSS  |  wscale  | SACK |        MAC         |
+/// +----------+-------+----------+------+--------------------+
+/// ```
+///
+/// The MAC is a keyed hash over the quad, the peer's ISN and the fields in
+/// front of it, so the ACK that completes the handshake can only echo a
+/// cookie we made.
+pub struct SynCookies {
+    secret: RandomState,
+    origin: Instant,
+    /// When we last sent a cookie, ACKs are only taken for one shortly after
+    last_sent: Option<Instant>,
+}
+
+impl SynCookies {
+    pub fn new() -> Self {
+        SynCookies {
+            secret: RandomState::new(),
+            origin: Instant::now(),
+            last_sent: None,
+        }
+    }
+
+    /// ISS for the SYN-ACK to a SYN with sequence number `irs`
+    pub fn encode(&mut self, quad: &Quad, irs: u32, syn: &SynOptions) -> u32 {
+        self.last_sent = Some(Instant::now());
+        let mss = MSS_TABLE
+            .iter()
+            .rposition(|&mss| mss as usize <= syn.mss)
+            .unwrap_or(0) as u32;
+        // 15 is past the largest shift count, it stands for no scaling
+        let wscale = syn.wscale.map_or(15, |shift| shift.min(MAX_WINDOW_SHIFT)) as u32;
+        let fields = (mss << 5) | (wscale << 1) | syn.sack_permitted as u32;
+        let period = self.period();
+        ((period & 0x1f) << 27) | (fields << MAC_BITS) | self.mac(quad, irs, period, fields)
+    }
+
+    /// What the SYN offered, if `cookie` is one we made for it recently
+    pub fn decode(&self, quad: &Quad, irs: u32, cookie: u32) -> Option<SynOptions> {
+        self.last_sent.filter(|at| at.elapsed() < 2 * PERIOD)?;
+
+        let now = self.period();
+        let period = [now, now.wrapping_sub(1)]
+            .into_iter()
+            .find(|period| period & 0x1f == cookie >> 27)?;
+        let fields = (cookie >> MAC_BITS) & 0xff;
+        if cookie & ((1 << MAC_BITS) - 1) != self.mac(quad, irs, period, fields) {
+            return None;
+        }
+
+        let wscale = ((fields >> 1) & 0xf) as u8;
+        Some(SynOptions {
+            mss: MSS_TABLE[(fields >> 5) as usize] as usize,
+            wscale: (wscale <= MAX_WINDOW_SHIFT).then_some(wscale),
+            sack_permitted: fields & 1 == 1,
+        })
+    }
+
+    /// Start of the clock the TSval of a cookie SYN-ACK comes from
+    pub fn ts_origin(&self) -> Instant {
+        self.origin
+    }
+
+    fn period(&self) -> u32 {
+        (self.origin.elapsed().as_secs() / PERIOD.as_secs()) as u32
+    }
+
+    fn mac(&self, quad: &Quad, irs: u32, period: u32, fields: u32) -> u32 {
+        self.secret.hash_one((quad, irs, period, fields)) as u32 & ((1 << MAC_BITS) - 1)
+    }
+}
+
+impl Default for SynCookies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn quad() -> Quad {
+        Quad {
+            src: (Ipv4Addr::new(192, 168, 0, 1), 40000),
+            dst: (Ipv4Addr::new(192, 168, 0, 2), 7000),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut cookies = SynCookies::new();
+        for syn in [
+            SynOptions {
+                mss: 1460,
+                wscale: Some(7),
+                sack_permitted: true,
+            },
+            SynOptions {
+                mss: 536,
+                wscale: None,
+                sack_permitted: false,
+            },
+            SynOptions {
+                mss: MIN_MSS,
+                wscale: Some(MAX_WINDOW_SHIFT),
+                sack_permitted: true,
+            },
+        ] {
+            let cookie = cookies.encode(&quad(), u32::MAX, &syn);
+            assert_eq!(cookies.decode(&quad(), u32::MAX, cookie), Some(syn));
+        }
+    }
+
+    #[test]
+    fn rounds_mss_down() {
+        let mut cookies = SynCookies::new();
+        for (mss, expected) in [(1459, 1452), (9000, 1460), (1000, 536), (100, MIN_MSS)] {
+            let syn = SynOptions {
+                mss,
+                wscale: None,
+                sack_permitted: false,
+            };
+            let cookie = cookies.encode(&quad(), 1, &syn);
+            let decoded = cookies.decode(&quad(), 1, cookie).unwrap();
+            assert_eq!(decoded.mss, expected);
+        }
+    }
+
+    #[test]
+    fn rejects_other_syns() {
+        let mut cookies = SynCookies::new();
+        let syn = SynOptions {
+            mss: 1460,
+            wscale: Some(7),
+            sack_permitted: true,
+        };
+        let cookie = cookies.encode(&quad(), 1000, &syn);
+        assert_eq!(cookies.decode(&quad(), 1001, cookie), None);
+
+        let mut other = quad();
+        other.src.1 += 1;
+        assert_eq!(cookies.decode(&other, 1000, cookie), None);
+        assert_eq!(cookies.decode(&quad(), 1000, cookie ^ 1), None);
+        // nor takes cookies of another instance
+        assert_eq!(SynCookies::new().decode(&quad(), 1000, cookie), None);
+    }
+}